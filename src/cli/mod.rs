@@ -1,17 +1,24 @@
-mod utils;
 use arrow::record_batch::RecordBatch;
 use arrow::util::pretty::print_batches;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use datafusion::prelude::*;
 use parquet::arrow::ArrowWriter;
 use parquet::file::properties::WriterProperties;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::fs::File;
 use std::sync::Arc;
-use utils::json_to_arrow;
+
+use crate::timon_engine::cloud_sync::{CloudStorageManager, ProgressCallback, RestoreMode, SinkGranularity, SyncProgress};
+use crate::timon_engine::db_manager::{DataFusionOutput, DatabaseManager, ReadOptions};
+use crate::timon_engine::helpers::{
+  json_to_arrow, list_partition_files, parse_date_bounds, parse_parquet_compression, query_parquet_file, read_parquet_schema,
+};
+use crate::timon_engine::udfs::builtin_udfs;
 
 /// CLI Tool for Converting JSON to Parquet and Executing SQL Queries
 #[derive(Parser)]
+#[allow(clippy::upper_case_acronyms)]
 #[command(name = "Parquet CLI")]
 #[command(author = "Ahmed Boutaraa")]
 #[command(version = "1.0.0")]
@@ -28,6 +35,9 @@ pub enum Commands {
     /// Input JSON file path
     input: String,
     output: String,
+    /// Codec of the Parquet file, as accepted by `set_compression` (e.g. snappy, zstd or zstd(9))
+    #[arg(long, default_value = "uncompressed")]
+    compression: String,
   },
   /// Execute SQL Query
   Query {
@@ -36,14 +46,132 @@ pub enum Commands {
     /// SQL query to execute
     query: String,
   },
+  /// Execute SQL Query against a Timon database, using the engine's partition-aware query
+  Db {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+    /// Database name
+    db: String,
+    /// Table name
+    table: String,
+    /// First partition date to include (YYYY-MM-DD), open when omitted
+    #[arg(long)]
+    start: Option<String>,
+    /// Last partition date to include (YYYY-MM-DD), open when omitted
+    #[arg(long)]
+    end: Option<String>,
+    /// SQL query to execute, defaults to selecting every row of the table
+    #[arg(long)]
+    query: Option<String>,
+  },
+  /// Print the DataFusion plan of a query against a Timon database
+  Explain {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+    /// Database name
+    db: String,
+    /// SQL query to explain
+    query: String,
+    /// First partition date to include (YYYY-MM-DD), open when omitted
+    #[arg(long)]
+    start: Option<String>,
+    /// Last partition date to include (YYYY-MM-DD), open when omitted
+    #[arg(long)]
+    end: Option<String>,
+    /// Run the query and report the actual metrics of each plan node
+    #[arg(long)]
+    analyze: bool,
+  },
+  /// List the databases and tables of a Timon storage with their file counts and sizes
+  Info {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+  },
+  /// Print the stored schema of a table and the physical schema of its latest Parquet file
+  Schema {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+    /// Database name
+    db: String,
+    /// Table name
+    table: String,
+  },
+  /// Upload the local Parquet files of a table to an S3 compatible bucket
+  Sink {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+    /// Database name
+    db: String,
+    /// Table name
+    table: String,
+    #[command(flatten)]
+    bucket: BucketArgs,
+    /// First date to upload (YYYY-MM-DD); with --end or --granularity, only the files in range are uploaded, even if synced before
+    #[arg(long)]
+    start: Option<String>,
+    /// Last date to upload (YYYY-MM-DD)
+    #[arg(long)]
+    end: Option<String>,
+    /// Files to upload in range: daily or monthly
+    #[arg(long)]
+    granularity: Option<String>,
+  },
+  /// Query a table's Parquet files stored in an S3 compatible bucket
+  Fetch {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+    /// Table name
+    table: String,
+    #[command(flatten)]
+    bucket: BucketArgs,
+    /// First date to fetch (YYYY-MM-DD)
+    #[arg(long)]
+    start: String,
+    /// Last date to fetch (YYYY-MM-DD)
+    #[arg(long)]
+    end: String,
+    /// SQL query to execute, defaults to selecting every row of the table
+    #[arg(long)]
+    query: Option<String>,
+  },
+  /// Rebuild a local database from the files sunk to an S3 compatible bucket
+  Restore {
+    /// Timon storage path (the directory holding metadata.json)
+    storage_path: String,
+    /// Database name
+    db: String,
+    #[command(flatten)]
+    bucket: BucketArgs,
+    /// What to do when the database exists locally: error, merge or overwrite
+    #[arg(long, default_value = "error")]
+    mode: String,
+  },
+}
+
+#[derive(Args)]
+pub struct BucketArgs {
+  /// Bucket endpoint URL
+  #[arg(long, default_value = "http://localhost:9000")]
+  pub endpoint: String,
+  /// Bucket name
+  #[arg(long, default_value = "timon")]
+  pub bucket: String,
+  /// Access key id
+  #[arg(long)]
+  pub key: String,
+  /// Secret access key
+  #[arg(long)]
+  pub secret: String,
 }
 
-pub fn convert_json_to_parquet(input: &str, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn convert_json_to_parquet(input: &str, output: &str, compression: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let compression = parse_parquet_compression(compression)?;
+
   // Read JSON file
   let file = File::open(input)?;
   let json_values: Vec<Value> = serde_json::from_reader(file)?;
 
-  // Convert JSON to Arrow format
+  // Convert JSON to Arrow with the engine's converter, so the file has the types of engine-written partition files
   let (arrays, schema) = json_to_arrow(&json_values)?;
 
   // Create a record batch
@@ -51,7 +179,7 @@ pub fn convert_json_to_parquet(input: &str, output: &str) -> Result<(), Box<dyn
 
   // Write to Parquet
   let output_file = File::create(output)?;
-  let props = WriterProperties::builder().build();
+  let props = WriterProperties::builder().set_compression(compression).build();
   let mut writer = ArrowWriter::try_new(output_file, batch.schema(), Some(props))?;
   writer.write(&batch)?;
   writer.close()?;
@@ -61,9 +189,242 @@ pub fn convert_json_to_parquet(input: &str, output: &str) -> Result<(), Box<dyn
 
 pub async fn execute_query(file: &str, query: &str) -> Result<(), Box<dyn std::error::Error>> {
   let ctx = SessionContext::new();
-  ctx.register_parquet("timon", file, ParquetReadOptions::default()).await?;
-  let df = ctx.sql(query).await?;
+  for udf in builtin_udfs() {
+    ctx.register_udf(udf);
+  }
+  let df = query_parquet_file(&ctx, file, query).await?;
   let results = df.collect().await?;
   let _ = print_batches(&results);
   Ok(())
 }
+
+pub async fn execute_db_query(
+  storage_path: &str,
+  db: &str,
+  table: &str,
+  start: Option<&str>,
+  end: Option<&str>,
+  query: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let db_manager = DatabaseManager::new(storage_path);
+  let sql_query = query.map(str::to_string).unwrap_or_else(|| format!("SELECT * FROM {}", table));
+
+  let mut date_range = HashMap::new();
+  date_range.insert("start_date".to_string(), start.unwrap_or("*").to_string());
+  date_range.insert("end_date".to_string(), end.unwrap_or("*").to_string());
+
+  if let DataFusionOutput::DataFrame(df) = db_manager.query(db, &sql_query, Some(date_range), false).await? {
+    let results = df.collect().await?;
+    let _ = print_batches(&results);
+  }
+  Ok(())
+}
+
+pub async fn explain_db_query(
+  storage_path: &str,
+  db: &str,
+  query: &str,
+  start: Option<&str>,
+  end: Option<&str>,
+  analyze: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let db_manager = DatabaseManager::new(storage_path);
+
+  let mut date_range = HashMap::new();
+  date_range.insert("start_date".to_string(), start.unwrap_or("*").to_string());
+  date_range.insert("end_date".to_string(), end.unwrap_or("*").to_string());
+
+  // Skipped files are already reported on stderr as they are registered
+  let (plan, _warnings) = db_manager.explain(db, query, Some(date_range), analyze).await?;
+  println!("{}", plan);
+  Ok(())
+}
+
+pub fn print_storage_info(storage_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let mut db_manager = DatabaseManager::new(storage_path);
+  let mut databases = db_manager.list_databases()?;
+  databases.sort();
+
+  for db in databases {
+    println!("{}", db);
+    for table in db_manager.list_tables_detailed(&db)? {
+      println!(
+        "  {:<24} files: {:<6} size: {:<12} rows: {:<10} dates: {} .. {}",
+        table.name,
+        table.file_count,
+        table.size_bytes,
+        table.row_count,
+        table.min_date.as_deref().unwrap_or("-"),
+        table.max_date.as_deref().unwrap_or("-")
+      );
+    }
+  }
+  Ok(())
+}
+
+pub fn print_table_schema(storage_path: &str, db: &str, table: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let mut db_manager = DatabaseManager::new(storage_path);
+  let details = db_manager
+    .list_tables_detailed(db)?
+    .into_iter()
+    .find(|details| details.name == table)
+    .ok_or_else(|| format!("Table '{}' not found in database '{}'", table, db))?;
+  println!("Stored schema:\n{}", serde_json::to_string_pretty(&details.schema)?);
+
+  // The physical schema comes from the most recent partition file, if the table holds any data
+  let table_path = db_manager.get_table_path(db, table)?.unwrap_or_default();
+  match list_partition_files(&table_path, table)?.last() {
+    Some((_, file_path)) => {
+      let file_schema = read_parquet_schema(file_path)?;
+      println!("\nParquet schema ({}, {} rows):", file_path, file_schema.rows);
+      for field in file_schema.arrow_schema.fields() {
+        println!("  {}: {} (nullable: {})", field.name(), field.data_type(), field.is_nullable());
+      }
+      println!("\nParquet columns:");
+      for column in &file_schema.columns {
+        println!(
+          "  {}: {} {} ({})",
+          column.name,
+          column.physical_type,
+          column.logical_type.as_deref().unwrap_or("-"),
+          column.repetition
+        );
+      }
+    }
+    None => println!("\nNo Parquet files for '{}.{}'", db, table),
+  }
+  Ok(())
+}
+
+fn cloud_storage_manager(storage_path: &str, bucket: &BucketArgs) -> CloudStorageManager {
+  CloudStorageManager::new(
+    DatabaseManager::new(storage_path),
+    Some(&bucket.endpoint),
+    Some(&bucket.key),
+    Some(&bucket.secret),
+    Some(&bucket.bucket),
+  )
+}
+
+// Print a line per synced file to stderr, leaving stdout to the JSON summary
+fn print_progress() -> ProgressCallback {
+  Arc::new(|progress: &SyncProgress| {
+    eprintln!(
+      "[{}/{}] {} {} ({} bytes)",
+      progress.files_done, progress.files_total, progress.operation, progress.file, progress.bytes_transferred
+    )
+  })
+}
+
+pub async fn sink_table(
+  storage_path: &str,
+  db: &str,
+  table: &str,
+  bucket: &BucketArgs,
+  start: Option<&str>,
+  end: Option<&str>,
+  granularity: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let cloud_manager = cloud_storage_manager(storage_path, bucket);
+  let summary = if start.is_none() && end.is_none() && granularity.is_none() {
+    cloud_manager.sink_daily_parquet(db, table, Some(&print_progress())).await?
+  } else {
+    let mut date_range = HashMap::new();
+    date_range.insert("start_date".to_string(), start.unwrap_or("*").to_string());
+    date_range.insert("end_date".to_string(), end.unwrap_or("*").to_string());
+    let (start_date, end_date) = parse_date_bounds(Some(&date_range))?;
+    let granularity = SinkGranularity::parse(granularity.unwrap_or("daily"))?;
+    cloud_manager
+      .sink_range(db, table, start_date, end_date, granularity, Some(&print_progress()))
+      .await?
+  };
+  println!("{}", serde_json::to_string_pretty(&summary)?);
+  Ok(())
+}
+
+pub async fn fetch_table(
+  storage_path: &str,
+  table: &str,
+  bucket: &BucketArgs,
+  start: &str,
+  end: &str,
+  query: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let cloud_manager = cloud_storage_manager(storage_path, bucket);
+  let sql_query = query.map(str::to_string).unwrap_or_else(|| format!("SELECT * FROM {}", table));
+
+  let mut date_range = HashMap::new();
+  date_range.insert("start_date".to_string(), start.to_string());
+  date_range.insert("end_date".to_string(), end.to_string());
+
+  if let DataFusionOutput::DataFrame(df) = cloud_manager.query_bucket(date_range, &sql_query, false, &ReadOptions::default()).await? {
+    let results = df.collect().await?;
+    let _ = print_batches(&results);
+  }
+  Ok(())
+}
+
+pub async fn restore_db(storage_path: &str, db: &str, bucket: &BucketArgs, mode: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let cloud_manager = cloud_storage_manager(storage_path, bucket);
+  let summary = cloud_manager.restore(db, RestoreMode::parse(mode)?, Some(&print_progress())).await?;
+  println!("{}", serde_json::to_string_pretty(&summary)?);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::timon_engine::helpers::read_parquet_arrow_schema;
+  use parquet::basic::Compression;
+  use parquet::file::reader::{FileReader, SerializedFileReader};
+  use serde_json::json;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn converted_files_have_the_types_of_engine_written_files() {
+    let dir = TempDir::new().unwrap();
+    let rows = json!([
+      {"device": "a", "count": 1, "temperature": 20.5, "online": true, "tags": ["x", "y"]},
+      {"device": "b", "count": 2, "temperature": 21.0, "online": false, "tags": ["z"]}
+    ]);
+    let schema = r#"{"device": {"type": "string"}, "count": {"type": "int"}, "temperature": {"type": "float"},
+      "online": {"type": "bool"}, "tags": {"type": "array", "items": "string"}}"#;
+    let mut manager = DatabaseManager::new(dir.path().to_str().unwrap());
+    manager.create_database("db").unwrap();
+    manager.create_table("db", "engine", schema).unwrap();
+    manager.create_table("db", "converted", schema).unwrap();
+    manager.insert("db", "engine", &rows.to_string()).unwrap();
+    let today = chrono::Utc::now().format("%Y-%m-%d");
+
+    let input = dir.path().join("rows.json");
+    std::fs::write(&input, rows.to_string()).unwrap();
+    let output = format!("{}/converted_{}.parquet", manager.table_dir("db", "converted"), today);
+    convert_json_to_parquet(input.to_str().unwrap(), &output, "uncompressed").unwrap();
+
+    let engine_file = format!("{}/engine_{}.parquet", manager.table_dir("db", "engine"), today);
+    let engine_schema = read_parquet_arrow_schema(&engine_file).unwrap();
+    let converted_schema = read_parquet_arrow_schema(&output).unwrap();
+    for field in engine_schema.fields() {
+      assert_eq!(converted_schema.field_with_name(field.name()).unwrap().data_type(), field.data_type());
+    }
+    let query = "SELECT device, count, temperature, online, tags FROM converted ORDER BY device";
+    let DataFusionOutput::Json(converted_rows) = manager.query("db", query, None, true).await.unwrap() else {
+      panic!("expected JSON rows");
+    };
+    assert_eq!(converted_rows, rows);
+  }
+
+  #[test]
+  fn converted_files_use_the_requested_codec() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("rows.json");
+    std::fs::write(&input, json!([{"device": "a", "count": 1}]).to_string()).unwrap();
+    let output = dir.path().join("rows.parquet");
+    let output = output.to_str().unwrap();
+
+    convert_json_to_parquet(input.to_str().unwrap(), output, "zstd").unwrap();
+    let reader = SerializedFileReader::new(File::open(output).unwrap()).unwrap();
+    assert!(matches!(reader.metadata().row_group(0).column(0).compression(), Compression::ZSTD(_)));
+    assert!(convert_json_to_parquet(input.to_str().unwrap(), output, "bogus").is_err());
+  }
+}
@@ -3,12 +3,23 @@ pub mod timon_engine;
 // cbindgen:ignore
 #[cfg(target_os = "android")]
 pub mod android {
-  use crate::timon_engine::{create_database, create_table, delete_database, delete_table, init_timon, insert, list_databases, list_tables, query};
-  use crate::timon_engine::{init_bucket, query_bucket, sink_daily_parquet};
-  use jni::objects::{JClass, JObject, JString, JValue};
-  use jni::sys::jstring;
+  use crate::timon_engine::cloud_sync::{ProgressCallback, SyncProgress};
+  use crate::timon_engine::{
+    attach_table, available_dates, checkpoint, compact_metadata, count_rows, create_database, create_database_with_options, create_table,
+    create_table_as, database_exists, database_size, delete_database, delete_table, detach_table, get_stats, init_timon, init_timon_read_only,
+    init_timon_with_encryption_key, init_timon_with_query_config, insert, insert_compressed, insert_ndjson, insert_ndjson_file, insert_with_mode,
+    list_databases, list_tables, list_tables_detailed, merge_tables, metadata_status, query_day, query_file, query_into, query_with_format,
+    query_with_options_json, recover, sample, set_compression, table_exists, table_version, truncate_table, upsert, version,
+  };
+  use crate::timon_engine::{
+    available_dates_bucket, ffi_error, ffi_response, init_bucket, init_bucket_with_config, query_bucket_with_format, query_bucket_with_read_options,
+    query_hybrid, reinit_bucket, set_sync_progress_callback, sink_daily_parquet, sink_range,
+  };
+  use jni::objects::{JByteArray, JClass, JObject, JString, JValue};
+  use jni::sys::{jint, jstring};
   use jni::JNIEnv;
   use std::collections::HashMap;
+  use std::sync::Arc;
   use tokio::runtime::Runtime;
 
   // ******************************** File Storage ********************************
@@ -16,36 +27,75 @@ pub mod android {
   pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initTimon(mut env: JNIEnv, _class: JClass, storage_path: JString) -> jstring {
     let rust_storage_path: String = env.get_string(&storage_path).expect("Couldn't get java string!").into();
 
-    match init_timon(&rust_storage_path) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to initialize Timon: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(init_timon(&rust_storage_path), "Failed to initialize Timon");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initTimonReadOnly(mut env: JNIEnv, _class: JClass, storage_path: JString) -> jstring {
+    let rust_storage_path: String = env.get_string(&storage_path).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(init_timon_read_only(&rust_storage_path), "Failed to initialize Timon");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initTimonWithConfig(
+    mut env: JNIEnv,
+    _class: JClass,
+    storage_path: JString,
+    query_config: JString,
+  ) -> jstring {
+    let rust_storage_path: String = env.get_string(&storage_path).expect("Couldn't get java string!").into();
+    let rust_query_config: String = env.get_string(&query_config).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      init_timon_with_query_config(&rust_storage_path, &rust_query_config),
+      "Failed to initialize Timon",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initTimonWithEncryptionKey(
+    mut env: JNIEnv,
+    _class: JClass,
+    storage_path: JString,
+    encryption_key: JString,
+  ) -> jstring {
+    let rust_storage_path: String = env.get_string(&storage_path).expect("Couldn't get java string!").into();
+    let rust_encryption_key: String = env.get_string(&encryption_key).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      init_timon_with_encryption_key(&rust_storage_path, &rust_encryption_key),
+      "Failed to initialize Timon",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
   pub unsafe extern "C" fn Java_com_rustexample_TimonModule_createDatabase(mut env: JNIEnv, _class: JClass, db_name: JString) -> jstring {
     let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
 
-    match create_database(&rust_db_name) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to create database: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(create_database(&rust_db_name), "Failed to create database");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_createDatabaseWithOptions(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    options_json: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_options_json: String = env.get_string(&options_json).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      create_database_with_options(&rust_db_name, &rust_options_json),
+      "Failed to create database",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
@@ -60,70 +110,140 @@ pub mod android {
     let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
     let rust_schema: String = env.get_string(&schema).expect("Couldn't get java string!").into();
 
-    match create_table(&rust_db_name, &rust_table_name, &rust_schema) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to create table: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(create_table(&rust_db_name, &rust_table_name, &rust_schema), "Failed to create table");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
   pub unsafe extern "C" fn Java_com_rustexample_TimonModule_listDatabases(env: JNIEnv, _class: JClass) -> jstring {
-    match list_databases() {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to list databases: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(list_databases(), "Failed to list databases");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_getVersion(env: JNIEnv, _class: JClass) -> jstring {
+    let response = ffi_response(version(), "Failed to get the version");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_getStats(env: JNIEnv, _class: JClass) -> jstring {
+    let response = ffi_response(get_stats(), "Failed to get the stats");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_getMetadataStatus(env: JNIEnv, _class: JClass) -> jstring {
+    let response = ffi_response(metadata_status(), "Failed to get the metadata status");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_compactMetadata(env: JNIEnv, _class: JClass) -> jstring {
+    let response = ffi_response(compact_metadata(), "Failed to compact the metadata");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_setCompression(mut env: JNIEnv, _class: JClass, codec: JString) -> jstring {
+    let rust_codec: String = env.get_string(&codec).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(set_compression(&rust_codec), "Failed to set the compression");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_checkpoint(env: JNIEnv, _class: JClass) -> jstring {
+    let response = ffi_response(checkpoint(), "Failed to checkpoint");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_recover(env: JNIEnv, _class: JClass) -> jstring {
+    let response = ffi_response(recover(), "Failed to replay the write-ahead logs");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
   pub unsafe extern "C" fn Java_com_rustexample_TimonModule_listTables(mut env: JNIEnv, _class: JClass, db_name: JString) -> jstring {
     let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
 
-    match list_tables(&rust_db_name) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to list tables: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(list_tables(&rust_db_name), "Failed to list tables");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_listTablesDetailed(mut env: JNIEnv, _class: JClass, db_name: JString) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(list_tables_detailed(&rust_db_name), "Failed to list tables details");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_databaseSize(mut env: JNIEnv, _class: JClass, db_name: JString) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(database_size(&rust_db_name), "Failed to get database size");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_databaseExists(mut env: JNIEnv, _class: JClass, db_name: JString) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(database_exists(&rust_db_name), "Failed to check database existence");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_tableExists(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(table_exists(&rust_db_name, &rust_table_name), "Failed to check table existence");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_availableDates(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(available_dates(&rust_db_name, &rust_table_name), "Failed to list available dates");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_tableVersion(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(table_version(&rust_db_name, &rust_table_name), "Failed to get table version");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
   pub unsafe extern "C" fn Java_com_rustexample_TimonModule_deleteDatabase(mut env: JNIEnv, _class: JClass, db_name: JString) -> jstring {
     let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
 
-    match delete_database(&rust_db_name) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to delete database: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(delete_database(&rust_db_name), "Failed to delete database");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
@@ -136,18 +256,78 @@ pub mod android {
     let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
     let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
 
-    match delete_table(&rust_db_name, &rust_table_name) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to delete table: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(delete_table(&rust_db_name, &rust_table_name), "Failed to delete table");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_truncateTable(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(truncate_table(&rust_db_name, &rust_table_name), "Failed to truncate table");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_mergeTables(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    source_table: JString,
+    dest_table: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_source_table: String = env.get_string(&source_table).expect("Couldn't get java string!").into();
+    let rust_dest_table: String = env.get_string(&dest_table).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      merge_tables(&rust_db_name, &rust_source_table, &rust_dest_table),
+      "Failed to merge tables",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_attachTable(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    source_dir: JString,
+    schema: JString,
+    options_json: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_source_dir: String = env.get_string(&source_dir).expect("Couldn't get java string!").into();
+    let rust_schema: String = env.get_string(&schema).expect("Couldn't get java string!").into();
+    let rust_options_json: String = env.get_string(&options_json).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      attach_table(&rust_db_name, &rust_table_name, &rust_source_dir, &rust_schema, &rust_options_json),
+      "Failed to attach table",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_detachTable(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(detach_table(&rust_db_name, &rust_table_name), "Failed to detach table");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
@@ -162,19 +342,110 @@ pub mod android {
     let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
     let rust_json_data: String = env.get_string(&json_data).expect("Couldn't get java string!").into();
 
-    match insert(&rust_db_name, &rust_table_name, &rust_json_data) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(e) => {
-        let error_message = env
-          .new_string(format!("Error writing JSON data to Parquet file: {:?}", e))
-          .expect("Couldn't create java string!");
-        error_message.into_raw()
-      }
-    }
+    let response = ffi_response(
+      insert(&rust_db_name, &rust_table_name, &rust_json_data),
+      "Error writing JSON data to Parquet file",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_insertWithMode(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    json_data: JString,
+    insert_mode: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_json_data: String = env.get_string(&json_data).expect("Couldn't get java string!").into();
+    let rust_insert_mode: String = env.get_string(&insert_mode).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      insert_with_mode(&rust_db_name, &rust_table_name, &rust_json_data, &rust_insert_mode),
+      "Error writing JSON data to Parquet file",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_upsert(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    json_data: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_json_data: String = env.get_string(&json_data).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      upsert(&rust_db_name, &rust_table_name, &rust_json_data),
+      "Error writing JSON data to Parquet file",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_insertNdjson(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    ndjson_data: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_ndjson_data: String = env.get_string(&ndjson_data).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      insert_ndjson(&rust_db_name, &rust_table_name, &rust_ndjson_data),
+      "Error writing NDJSON data to Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_insertNdjsonFile(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    file_path: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_file_path: String = env.get_string(&file_path).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      insert_ndjson_file(&rust_db_name, &rust_table_name, &rust_file_path),
+      "Error writing NDJSON file to Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_insertCompressed(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    data: JByteArray,
+    codec: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_data: Vec<u8> = env.convert_byte_array(&data).expect("Couldn't get java byte array!");
+    let rust_codec: String = env.get_string(&codec).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      insert_compressed(&rust_db_name, &rust_table_name, &rust_data, &rust_codec),
+      "Error writing compressed JSON to Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   fn get_date_range_value(env: &mut JNIEnv, date_range: &JObject, key: &str) -> String {
@@ -216,345 +487,1180 @@ pub mod android {
     db_name: JString,
     sql_query: JString,
     _date_range: JObject, // TODO: Utilize date_range to optionally enhance the query by limiting the parquet files included based on the specified date range.
+    format: JString,
   ) -> jstring {
     // Convert Java strings to Rust strings
     let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
     let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_format: String = env.get_string(&format).expect("Couldn't get java string!").into();
 
-    match Runtime::new().unwrap().block_on(query(&rust_db_name, &rust_sql_query, None)) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(e) => {
-        let error_message = env
-          .new_string(format!("Error querying Parquet files: {:?}", e))
-          .expect("Couldn't create java string!");
-        error_message.into_raw()
-      }
-    }
+    let response = ffi_response(
+      Runtime::new()
+        .unwrap()
+        .block_on(query_with_format(&rust_db_name, &rust_sql_query, None, &rust_format)),
+      "Error querying Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
-  // ******************************** S3 Compatible Storage ********************************
   #[no_mangle]
-  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initBucket(
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryDay(
     mut env: JNIEnv,
     _class: JClass,
-    bucket_endpoint: JString,
-    bucket_name: JString,
-    access_key_id: JString,
-    secret_access_key: JString,
+    db_name: JString,
+    table_name: JString,
+    date: JString,
+    sql_query: JString,
+    format: JString,
   ) -> jstring {
-    let rust_bucket_endpoint: String = env.get_string(&bucket_endpoint).expect("Couldn't get java string!").into();
-    let rust_bucket_name: String = env.get_string(&bucket_name).expect("Couldn't get java string!").into();
-    let rust_access_key_id: String = env.get_string(&access_key_id).expect("Couldn't get java string!").into();
-    let rust_secret_access_key: String = env.get_string(&secret_access_key).expect("Couldn't get java string!").into();
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_date: String = env.get_string(&date).expect("Couldn't get java string!").into();
+    let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_format: String = env.get_string(&format).expect("Couldn't get java string!").into();
 
-    match init_bucket(&rust_bucket_endpoint, &rust_bucket_name, &rust_access_key_id, &rust_secret_access_key) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed to initialize S3 bucket: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
-      }
-    }
+    let response = ffi_response(
+      Runtime::new()
+        .unwrap()
+        .block_on(query_day(&rust_db_name, &rust_table_name, &rust_date, &rust_sql_query, &rust_format)),
+      "Error querying Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
   }
 
   #[no_mangle]
-  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryBucket(
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryFile(
     mut env: JNIEnv,
     _class: JClass,
-    date_range: JObject,
+    file_path: JString,
     sql_query: JString,
+    format: JString,
   ) -> jstring {
-    // Convert Java strings to Rust strings
+    let rust_file_path: String = env.get_string(&file_path).expect("Couldn't get java string!").into();
     let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_format: String = env.get_string(&format).expect("Couldn't get java string!").into();
 
-    let mut rust_date_range: HashMap<&str, &str> = HashMap::new();
-    let rust_start = get_date_range_value(&mut env, &date_range, "start");
-    let rust_end = get_date_range_value(&mut env, &date_range, "end");
-    rust_date_range.insert("start_date", &rust_start);
+    let response = ffi_response(
+      Runtime::new()
+        .unwrap()
+        .block_on(query_file(&rust_file_path, &rust_sql_query, &rust_format)),
+      "Error querying Parquet file",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryWithOptions(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    sql_query: JString,
+    date_range: JObject,
+    options: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_options: String = env.get_string(&options).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<String, String> = HashMap::new();
+    rust_date_range.insert("start_date".to_owned(), get_date_range_value(&mut env, &date_range, "start"));
+    rust_date_range.insert("end_date".to_owned(), get_date_range_value(&mut env, &date_range, "end"));
+
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(query_with_options_json(
+        &rust_db_name,
+        &rust_sql_query,
+        Some(rust_date_range),
+        &rust_options,
+      )),
+      "Error querying Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_sample(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    date_range: JObject,
+    n: jint,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<String, String> = HashMap::new();
+    rust_date_range.insert("start_date".to_owned(), get_date_range_value(&mut env, &date_range, "start"));
+    rust_date_range.insert("end_date".to_owned(), get_date_range_value(&mut env, &date_range, "end"));
+
+    let response = ffi_response(
+      sample(&rust_db_name, &rust_table_name, Some(rust_date_range), n.max(0) as usize),
+      "Error sampling Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_countRows(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    date_range: JObject,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<String, String> = HashMap::new();
+    rust_date_range.insert("start_date".to_owned(), get_date_range_value(&mut env, &date_range, "start"));
+    rust_date_range.insert("end_date".to_owned(), get_date_range_value(&mut env, &date_range, "end"));
+
+    let response = ffi_response(
+      Runtime::new()
+        .unwrap()
+        .block_on(count_rows(&rust_db_name, &rust_table_name, Some(rust_date_range))),
+      "Error counting rows",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryInto(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    sql_query: JString,
+    date_range: JObject,
+    dest_db_name: JString,
+    dest_table_name: JString,
+    options: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_dest_db_name: String = env.get_string(&dest_db_name).expect("Couldn't get java string!").into();
+    let rust_dest_table_name: String = env.get_string(&dest_table_name).expect("Couldn't get java string!").into();
+    let rust_options: String = env.get_string(&options).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<String, String> = HashMap::new();
+    rust_date_range.insert("start_date".to_owned(), get_date_range_value(&mut env, &date_range, "start"));
+    rust_date_range.insert("end_date".to_owned(), get_date_range_value(&mut env, &date_range, "end"));
+
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(query_into(
+        &rust_db_name,
+        &rust_sql_query,
+        Some(rust_date_range),
+        &rust_dest_db_name,
+        &rust_dest_table_name,
+        &rust_options,
+      )),
+      "Error writing the query result to a table",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_createTableAs(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    date_range: JObject,
+    select_sql: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_select_sql: String = env.get_string(&select_sql).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<String, String> = HashMap::new();
+    rust_date_range.insert("start_date".to_owned(), get_date_range_value(&mut env, &date_range, "start"));
+    rust_date_range.insert("end_date".to_owned(), get_date_range_value(&mut env, &date_range, "end"));
+
+    let response = ffi_response(
+      Runtime::new()
+        .unwrap()
+        .block_on(create_table_as(&rust_db_name, &rust_table_name, Some(rust_date_range), &rust_select_sql)),
+      "Error creating the table from the query",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  // ******************************** S3 Compatible Storage ********************************
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initBucket(
+    mut env: JNIEnv,
+    _class: JClass,
+    bucket_endpoint: JString,
+    bucket_name: JString,
+    access_key_id: JString,
+    secret_access_key: JString,
+  ) -> jstring {
+    let rust_bucket_endpoint: String = env.get_string(&bucket_endpoint).expect("Couldn't get java string!").into();
+    let rust_bucket_name: String = env.get_string(&bucket_name).expect("Couldn't get java string!").into();
+    let rust_access_key_id: String = env.get_string(&access_key_id).expect("Couldn't get java string!").into();
+    let rust_secret_access_key: String = env.get_string(&secret_access_key).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      init_bucket(&rust_bucket_endpoint, &rust_bucket_name, &rust_access_key_id, &rust_secret_access_key),
+      "Failed to initialize S3 bucket",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_initBucketWithConfig(
+    mut env: JNIEnv,
+    _class: JClass,
+    bucket_endpoint: JString,
+    bucket_name: JString,
+    access_key_id: JString,
+    secret_access_key: JString,
+    client_config: JString,
+  ) -> jstring {
+    let rust_bucket_endpoint: String = env.get_string(&bucket_endpoint).expect("Couldn't get java string!").into();
+    let rust_bucket_name: String = env.get_string(&bucket_name).expect("Couldn't get java string!").into();
+    let rust_access_key_id: String = env.get_string(&access_key_id).expect("Couldn't get java string!").into();
+    let rust_secret_access_key: String = env.get_string(&secret_access_key).expect("Couldn't get java string!").into();
+    let rust_client_config: String = env.get_string(&client_config).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      init_bucket_with_config(
+        &rust_bucket_endpoint,
+        &rust_bucket_name,
+        &rust_access_key_id,
+        &rust_secret_access_key,
+        &rust_client_config,
+      ),
+      "Failed to initialize S3 bucket",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_reinitBucket(
+    mut env: JNIEnv,
+    _class: JClass,
+    bucket_endpoint: JString,
+    bucket_name: JString,
+    access_key_id: JString,
+    secret_access_key: JString,
+  ) -> jstring {
+    let rust_bucket_endpoint: String = env.get_string(&bucket_endpoint).expect("Couldn't get java string!").into();
+    let rust_bucket_name: String = env.get_string(&bucket_name).expect("Couldn't get java string!").into();
+    let rust_access_key_id: String = env.get_string(&access_key_id).expect("Couldn't get java string!").into();
+    let rust_secret_access_key: String = env.get_string(&secret_access_key).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      reinit_bucket(&rust_bucket_endpoint, &rust_bucket_name, &rust_access_key_id, &rust_secret_access_key),
+      "Failed to reinitialize S3 bucket",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryBucket(
+    mut env: JNIEnv,
+    _class: JClass,
+    date_range: JObject,
+    sql_query: JString,
+    format: JString,
+  ) -> jstring {
+    // Convert Java strings to Rust strings
+    let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_format: String = env.get_string(&format).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<&str, &str> = HashMap::new();
+    let rust_start = get_date_range_value(&mut env, &date_range, "start");
+    let rust_end = get_date_range_value(&mut env, &date_range, "end");
+    rust_date_range.insert("start_date", &rust_start);
+    rust_date_range.insert("end_date", &rust_end);
+
+    let response = ffi_response(
+      Runtime::new()
+        .unwrap()
+        .block_on(query_bucket_with_format(rust_date_range, &rust_sql_query, &rust_format)),
+      "Error querying Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryBucketWithReadOptions(
+    mut env: JNIEnv,
+    _class: JClass,
+    date_range: JObject,
+    sql_query: JString,
+    format: JString,
+    read_options: JString,
+  ) -> jstring {
+    let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_format: String = env.get_string(&format).expect("Couldn't get java string!").into();
+    let rust_read_options: String = env.get_string(&read_options).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<&str, &str> = HashMap::new();
+    let rust_start = get_date_range_value(&mut env, &date_range, "start");
+    let rust_end = get_date_range_value(&mut env, &date_range, "end");
+    rust_date_range.insert("start_date", &rust_start);
+    rust_date_range.insert("end_date", &rust_end);
+
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(query_bucket_with_read_options(
+        rust_date_range,
+        &rust_sql_query,
+        &rust_format,
+        &rust_read_options,
+      )),
+      "Error querying Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_queryHybrid(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    date_range: JObject,
+    sql_query: JString,
+    local_retention_days: jint,
+    format: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_sql_query: String = env.get_string(&sql_query).expect("Couldn't get java string!").into();
+    let rust_format: String = env.get_string(&format).expect("Couldn't get java string!").into();
+
+    let mut rust_date_range: HashMap<&str, &str> = HashMap::new();
+    let rust_start = get_date_range_value(&mut env, &date_range, "start");
+    let rust_end = get_date_range_value(&mut env, &date_range, "end");
+    rust_date_range.insert("start_date", &rust_start);
     rust_date_range.insert("end_date", &rust_end);
 
-    match Runtime::new().unwrap().block_on(query_bucket(rust_date_range, &rust_sql_query)) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(query_hybrid(
+        &rust_db_name,
+        &rust_table_name,
+        rust_date_range,
+        &rust_sql_query,
+        local_retention_days.max(0) as u64,
+        &rust_format,
+      )),
+      "Error querying local and bucket Parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_availableDatesBucket(mut env: JNIEnv, _class: JClass, table_name: JString) -> jstring {
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(available_dates_bucket(&rust_table_name)),
+      "Failed to list available dates in bucket",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_sinkRange(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+    start_date: JString,
+    end_date: JString,
+    granularity: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+    let rust_start_date: String = env.get_string(&start_date).expect("Couldn't get java string!").into();
+    let rust_end_date: String = env.get_string(&end_date).expect("Couldn't get java string!").into();
+    let rust_granularity: String = env.get_string(&granularity).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(sink_range(
+        &rust_db_name,
+        &rust_table_name,
+        &rust_start_date,
+        &rust_end_date,
+        &rust_granularity,
+      )),
+      "Failed to sink the date range",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_sinkMonthlyParquet(
+    mut env: JNIEnv,
+    _class: JClass,
+    db_name: JString,
+    table_name: JString,
+  ) -> jstring {
+    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
+    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
+
+    let response = ffi_response(
+      Runtime::new().unwrap().block_on(sink_daily_parquet(&rust_db_name, &rust_table_name)),
+      "Failed sink monthly parquet files",
+    );
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+
+  // Register `listener.onProgress(String)`, called with the JSON of each `SyncProgress` during a sink; a null listener
+  // removes it. Exceptions thrown by the listener are cleared so they don't abort the sync.
+  #[no_mangle]
+  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_setSyncProgressCallback(env: JNIEnv, _class: JClass, listener: JObject) -> jstring {
+    let callback: Option<ProgressCallback> = if listener.is_null() {
+      None
+    } else {
+      match (env.get_java_vm(), env.new_global_ref(&listener)) {
+        (Ok(vm), Ok(listener)) => Some(Arc::new(move |progress: &SyncProgress| {
+          let Ok(mut env) = vm.attach_current_thread() else {
+            return;
+          };
+          let Ok(progress_json) = env.new_string(serde_json::to_string(progress).unwrap_or_default()) else {
+            return;
+          };
+          if env
+            .call_method(&listener, "onProgress", "(Ljava/lang/String;)V", &[JValue::from(&progress_json)])
+            .is_err()
+          {
+            let _ = env.exception_clear();
+          }
+        })),
+        _ => {
+          let response = ffi_error("Couldn't keep a reference to the progress listener");
+          return env.new_string(response).expect("Couldn't create java string!").into_raw();
+        }
       }
-      Err(e) => {
-        let error_message = env
-          .new_string(format!("Error querying Parquet files: {:?}", e))
-          .expect("Couldn't create java string!");
-        error_message.into_raw()
+    };
+    let response = ffi_response(set_sync_progress_callback(callback), "Failed to set the sync progress callback");
+    env.new_string(response).expect("Couldn't create java string!").into_raw()
+  }
+}
+
+#[cfg(target_os = "ios")]
+pub mod ios {
+  use crate::timon_engine::cloud_sync::{ProgressCallback, SyncProgress};
+  use crate::timon_engine::{
+    attach_table, available_dates, checkpoint, compact_metadata, count_rows, create_database, create_database_with_options, create_table,
+    create_table_as, database_exists, database_size, delete_database, delete_table, detach_table, get_stats, init_timon, init_timon_read_only,
+    init_timon_with_encryption_key, init_timon_with_query_config, insert, insert_compressed, insert_ndjson, insert_ndjson_file, insert_with_mode,
+    list_databases, list_tables, list_tables_detailed, merge_tables, metadata_status, query_day, query_file, query_into, query_with_format,
+    query_with_options_json, recover, sample, set_compression, table_exists, table_version, truncate_table, upsert, version,
+  };
+  use crate::timon_engine::{
+    available_dates_bucket, ffi_error, ffi_response, init_bucket, init_bucket_with_config, query_bucket_with_format, query_bucket_with_read_options,
+    query_hybrid, reinit_bucket, set_sync_progress_callback, sink_daily_parquet, sink_range,
+  };
+  use libc::c_char;
+  use std::collections::HashMap;
+  use std::ffi::{CStr, CString};
+  use std::sync::Arc;
+  use tokio::runtime::Runtime;
+
+  // Helper function to convert C strings to Rust strings
+  unsafe fn c_str_to_string(c_str: *const c_char) -> Result<String, String> {
+    if c_str.is_null() {
+      Err("Null pointer received".to_string())
+    } else {
+      CStr::from_ptr(c_str)
+        .to_str()
+        .map(|s| s.to_string())
+        .map_err(|e| format!("Failed to convert C string to Rust string: {:?}", e))
+    }
+  }
+
+  // Helper function to convert Rust strings to C strings
+  fn string_to_c_str(s: String) -> *mut c_char {
+    CString::new(s).unwrap().into_raw()
+  }
+
+  #[no_mangle]
+  pub extern "C" fn rust_string_free(s: *mut c_char) {
+    if !s.is_null() {
+      unsafe {
+        CString::from_raw(s);
+      }
+    }
+  }
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_initTimon(storage_path: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(storage_path) {
+        Ok(rust_storage_path) => string_to_c_str(ffi_response(init_timon(&rust_storage_path), "Failed to initialize Timon")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_initTimonReadOnly(storage_path: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(storage_path) {
+        Ok(rust_storage_path) => string_to_c_str(ffi_response(init_timon_read_only(&rust_storage_path), "Failed to initialize Timon")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_initTimonWithConfig(storage_path: *const c_char, query_config: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(storage_path), c_str_to_string(query_config)) {
+        (Ok(rust_storage_path), Ok(rust_query_config)) => string_to_c_str(ffi_response(
+          init_timon_with_query_config(&rust_storage_path, &rust_query_config),
+          "Failed to initialize Timon",
+        )),
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_initTimonWithEncryptionKey(
+    storage_path: *const c_char,
+    encryption_key: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(storage_path), c_str_to_string(encryption_key)) {
+        (Ok(rust_storage_path), Ok(rust_encryption_key)) => string_to_c_str(ffi_response(
+          init_timon_with_encryption_key(&rust_storage_path, &rust_encryption_key),
+          "Failed to initialize Timon",
+        )),
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_createDatabase(db_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(db_name) {
+        Ok(rust_db_name) => string_to_c_str(ffi_response(create_database(&rust_db_name), "Failed to create database")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_createDatabaseWithOptions(db_name: *const c_char, options_json: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(options_json)) {
+        (Ok(rust_db_name), Ok(rust_options_json)) => string_to_c_str(ffi_response(
+          create_database_with_options(&rust_db_name, &rust_options_json),
+          "Failed to create database",
+        )),
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_createTable(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    schema: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(schema)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_schema)) => string_to_c_str(ffi_response(
+          create_table(&rust_db_name, &rust_table_name, &rust_schema),
+          "Failed to create table",
+        )),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_listDatabases() -> *mut c_char {
+    string_to_c_str(ffi_response(list_databases(), "Failed to list databases"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_getVersion() -> *mut c_char {
+    string_to_c_str(ffi_response(version(), "Failed to get the version"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_getStats() -> *mut c_char {
+    string_to_c_str(ffi_response(get_stats(), "Failed to get the stats"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_getMetadataStatus() -> *mut c_char {
+    string_to_c_str(ffi_response(metadata_status(), "Failed to get the metadata status"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_compactMetadata() -> *mut c_char {
+    string_to_c_str(ffi_response(compact_metadata(), "Failed to compact the metadata"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_setCompression(codec: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(codec) {
+        Ok(rust_codec) => string_to_c_str(ffi_response(set_compression(&rust_codec), "Failed to set the compression")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_checkpoint() -> *mut c_char {
+    string_to_c_str(ffi_response(checkpoint(), "Failed to checkpoint"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_recover() -> *mut c_char {
+    string_to_c_str(ffi_response(recover(), "Failed to replay the write-ahead logs"))
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_listTables(db_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(db_name) {
+        Ok(rust_db_name) => string_to_c_str(ffi_response(list_tables(&rust_db_name), "Failed to list tables")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_listTablesDetailed(db_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(db_name) {
+        Ok(rust_db_name) => string_to_c_str(ffi_response(list_tables_detailed(&rust_db_name), "Failed to list tables details")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_databaseSize(db_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(db_name) {
+        Ok(rust_db_name) => string_to_c_str(ffi_response(database_size(&rust_db_name), "Failed to get database size")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_databaseExists(db_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(db_name) {
+        Ok(rust_db_name) => string_to_c_str(ffi_response(database_exists(&rust_db_name), "Failed to check database existence")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_tableExists(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
+        (Ok(rust_db_name), Ok(rust_table_name)) => string_to_c_str(ffi_response(
+          table_exists(&rust_db_name, &rust_table_name),
+          "Failed to check table existence",
+        )),
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_availableDates(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
+        (Ok(rust_db_name), Ok(rust_table_name)) => string_to_c_str(ffi_response(
+          available_dates(&rust_db_name, &rust_table_name),
+          "Failed to list available dates",
+        )),
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_tableVersion(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
+        (Ok(rust_db_name), Ok(rust_table_name)) => string_to_c_str(ffi_response(
+          table_version(&rust_db_name, &rust_table_name),
+          "Failed to get table version",
+        )),
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_deleteDatabase(db_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(db_name) {
+        Ok(rust_db_name) => string_to_c_str(ffi_response(delete_database(&rust_db_name), "Failed to delete database")),
+        Err(err) => string_to_c_str(ffi_error(&err)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_deleteTable(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
+        (Ok(rust_db_name), Ok(rust_table_name)) => {
+          string_to_c_str(ffi_response(delete_table(&rust_db_name, &rust_table_name), "Failed to delete table"))
+        }
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_truncateTable(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
+        (Ok(rust_db_name), Ok(rust_table_name)) => {
+          string_to_c_str(ffi_response(truncate_table(&rust_db_name, &rust_table_name), "Failed to truncate table"))
+        }
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_mergeTables(
+    db_name: *const c_char,
+    source_table: *const c_char,
+    dest_table: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(source_table), c_str_to_string(dest_table)) {
+        (Ok(rust_db_name), Ok(rust_source_table), Ok(rust_dest_table)) => string_to_c_str(ffi_response(
+          merge_tables(&rust_db_name, &rust_source_table, &rust_dest_table),
+          "Failed to merge tables",
+        )),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_attachTable(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    source_dir: *const c_char,
+    schema: *const c_char,
+    options_json: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(table_name),
+        c_str_to_string(source_dir),
+        c_str_to_string(schema),
+        c_str_to_string(options_json),
+      ) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_source_dir), Ok(rust_schema), Ok(rust_options_json)) => string_to_c_str(ffi_response(
+          attach_table(&rust_db_name, &rust_table_name, &rust_source_dir, &rust_schema, &rust_options_json),
+          "Failed to attach table",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_detachTable(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
+        (Ok(rust_db_name), Ok(rust_table_name)) => {
+          string_to_c_str(ffi_response(detach_table(&rust_db_name, &rust_table_name), "Failed to detach table"))
+        }
+        (Err(e), _) | (_, Err(e)) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_insert(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    json_data: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(json_data)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_json_data)) => string_to_c_str(ffi_response(
+          insert(&rust_db_name, &rust_table_name, &rust_json_data),
+          "Error writing JSON data to Parquet file",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub unsafe extern "C" fn Java_com_rustexample_TimonModule_sinkMonthlyParquet(
-    mut env: JNIEnv,
-    _class: JClass,
-    db_name: JString,
-    table_name: JString,
-  ) -> jstring {
-    let rust_db_name: String = env.get_string(&db_name).expect("Couldn't get java string!").into();
-    let rust_table_name: String = env.get_string(&table_name).expect("Couldn't get java string!").into();
-
-    match Runtime::new().unwrap().block_on(sink_daily_parquet(&rust_db_name, &rust_table_name)) {
-      Ok(result) => {
-        let json_string = result.to_string();
-        let output = env.new_string(json_string).expect("Couldn't create success string!");
-        output.into_raw()
-      }
-      Err(err) => {
-        let err_message = format!("Failed sink monthly parquet files: {:?}", err);
-        let output = env.new_string(err_message).expect("Couldn't create error string!");
-        output.into_raw()
+  pub extern "C" fn Java_com_rustexample_TimonModule_upsert(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    json_data: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(json_data)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_json_data)) => string_to_c_str(ffi_response(
+          upsert(&rust_db_name, &rust_table_name, &rust_json_data),
+          "Error writing JSON data to Parquet file",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
-}
-
-#[cfg(target_os = "ios")]
-pub mod ios {
-  use crate::timon_engine::{create_database, create_table, delete_database, delete_table, init_timon, insert, list_databases, list_tables, query};
-  use crate::timon_engine::{init_bucket, query_bucket, sink_daily_parquet};
-  use libc::c_char;
-  use std::collections::HashMap;
-  use std::ffi::{CStr, CString};
-  use tokio::runtime::Runtime;
 
-  // Helper function to convert C strings to Rust strings
-  unsafe fn c_str_to_string(c_str: *const c_char) -> Result<String, String> {
-    if c_str.is_null() {
-      Err("Null pointer received".to_string())
-    } else {
-      CStr::from_ptr(c_str)
-        .to_str()
-        .map(|s| s.to_string())
-        .map_err(|e| format!("Failed to convert C string to Rust string: {:?}", e))
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_insertWithMode(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    json_data: *const c_char,
+    insert_mode: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(table_name),
+        c_str_to_string(json_data),
+        c_str_to_string(insert_mode),
+      ) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_json_data), Ok(rust_insert_mode)) => string_to_c_str(ffi_response(
+          insert_with_mode(&rust_db_name, &rust_table_name, &rust_json_data, &rust_insert_mode),
+          "Error writing JSON data to Parquet file",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
     }
   }
 
-  // Helper function to convert Rust strings to C strings
-  fn string_to_c_str(s: String) -> *mut c_char {
-    CString::new(s).unwrap().into_raw()
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_insertNdjson(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    ndjson_data: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(ndjson_data)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_ndjson_data)) => string_to_c_str(ffi_response(
+          insert_ndjson(&rust_db_name, &rust_table_name, &rust_ndjson_data),
+          "Error writing NDJSON data to Parquet files",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
   }
 
   #[no_mangle]
-  pub extern "C" fn rust_string_free(s: *mut c_char) {
-    if !s.is_null() {
-      unsafe {
-        CString::from_raw(s);
+  pub extern "C" fn Java_com_rustexample_TimonModule_insertNdjsonFile(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    file_path: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(file_path)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_file_path)) => string_to_c_str(ffi_response(
+          insert_ndjson_file(&rust_db_name, &rust_table_name, &rust_file_path),
+          "Error writing NDJSON file to Parquet files",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
+
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_initTimon(storage_path: *const c_char) -> *mut c_char {
+  pub extern "C" fn Java_com_rustexample_TimonModule_insertCompressed(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    data: *const u8,
+    data_len: usize,
+    codec: *const c_char,
+  ) -> *mut c_char {
     unsafe {
-      match c_str_to_string(storage_path) {
-        Ok(rust_storage_path) => match init_timon(&rust_storage_path) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to initialize Timon: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        Err(err) => {
-          let err_message = serde_json::json!({ "error": err }).to_string();
-          string_to_c_str(err_message)
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(codec)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_codec)) if !data.is_null() || data_len == 0 => {
+          let rust_data = if data_len == 0 {
+            &[][..]
+          } else {
+            std::slice::from_raw_parts(data, data_len)
+          };
+          string_to_c_str(ffi_response(
+            insert_compressed(&rust_db_name, &rust_table_name, rust_data, &rust_codec),
+            "Error writing compressed JSON to Parquet files",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_createDatabase(db_name: *const c_char) -> *mut c_char {
+  pub extern "C" fn Java_com_rustexample_TimonModule_query(
+    db_name: *const c_char,
+    sql_query: *const c_char,
+    date_range_json: *const c_char, // TODO: Utilize date_range to optionally enhance the query by limiting the parquet files included based on the specified date range.
+    format: *const c_char,
+  ) -> *mut c_char {
     unsafe {
-      match c_str_to_string(db_name) {
-        Ok(rust_db_name) => match create_database(&rust_db_name) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to create database: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        Err(err) => {
-          let err_message = serde_json::json!({ "error": err }).to_string();
-          string_to_c_str(err_message)
-        }
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(sql_query),
+        c_str_to_string(date_range_json),
+        c_str_to_string(format),
+      ) {
+        (Ok(rust_db_name), Ok(rust_sql_query), Ok(_rust_date_range_json), Ok(rust_format)) => string_to_c_str(ffi_response(
+          Runtime::new()
+            .unwrap()
+            .block_on(query_with_format(&rust_db_name, &rust_sql_query, None, &rust_format)),
+          "Error querying Parquet files",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_createTable(
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryDay(
     db_name: *const c_char,
     table_name: *const c_char,
-    schema: *const c_char,
+    date: *const c_char,
+    sql_query: *const c_char,
+    format: *const c_char,
   ) -> *mut c_char {
     unsafe {
-      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(schema)) {
-        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_schema)) => match create_table(&rust_db_name, &rust_table_name, &rust_schema) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to create table: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
-          let err_message = serde_json::json!({ "error": e }).to_string();
-          string_to_c_str(err_message)
-        }
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(table_name),
+        c_str_to_string(date),
+        c_str_to_string(sql_query),
+        c_str_to_string(format),
+      ) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_date), Ok(rust_sql_query), Ok(rust_format)) => string_to_c_str(ffi_response(
+          Runtime::new()
+            .unwrap()
+            .block_on(query_day(&rust_db_name, &rust_table_name, &rust_date, &rust_sql_query, &rust_format)),
+          "Error querying Parquet files",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_listDatabases() -> *mut c_char {
-    match list_databases() {
-      Ok(result) => {
-        let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "[]".to_string());
-        string_to_c_str(json_string)
-      }
-      Err(err) => {
-        let err_message = serde_json::json!({ "error": format!("Failed to list databases: {:?}", err) }).to_string();
-        string_to_c_str(err_message)
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryFile(
+    file_path: *const c_char,
+    sql_query: *const c_char,
+    format: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (c_str_to_string(file_path), c_str_to_string(sql_query), c_str_to_string(format)) {
+        (Ok(rust_file_path), Ok(rust_sql_query), Ok(rust_format)) => string_to_c_str(ffi_response(
+          Runtime::new()
+            .unwrap()
+            .block_on(query_file(&rust_file_path, &rust_sql_query, &rust_format)),
+          "Error querying Parquet file",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_listTables(db_name: *const c_char) -> *mut c_char {
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryWithOptions(
+    db_name: *const c_char,
+    sql_query: *const c_char,
+    date_range_json: *const c_char,
+    options: *const c_char,
+  ) -> *mut c_char {
     unsafe {
-      match c_str_to_string(db_name) {
-        Ok(rust_db_name) => match list_tables(&rust_db_name) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "[]".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to list tables: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        Err(err) => {
-          let err_message = serde_json::json!({ "error": err }).to_string();
-          string_to_c_str(err_message)
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(sql_query),
+        c_str_to_string(date_range_json),
+        c_str_to_string(options),
+      ) {
+        (Ok(rust_db_name), Ok(rust_sql_query), Ok(rust_date_range_json), Ok(rust_options)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert(
+            "start_date".to_owned(),
+            rust_date_range.get("start").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+          date_range_map.insert(
+            "end_date".to_owned(),
+            rust_date_range.get("end").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+
+          string_to_c_str(ffi_response(
+            Runtime::new().unwrap().block_on(query_with_options_json(
+              &rust_db_name,
+              &rust_sql_query,
+              Some(date_range_map),
+              &rust_options,
+            )),
+            "Error querying Parquet files",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_deleteDatabase(db_name: *const c_char) -> *mut c_char {
+  pub extern "C" fn Java_com_rustexample_TimonModule_sample(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    date_range_json: *const c_char,
+    n: u32,
+  ) -> *mut c_char {
     unsafe {
-      match c_str_to_string(db_name) {
-        Ok(rust_db_name) => match delete_database(&rust_db_name) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to delete database: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        Err(err) => {
-          let err_message = serde_json::json!({ "error": err }).to_string();
-          string_to_c_str(err_message)
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(date_range_json)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_date_range_json)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert(
+            "start_date".to_owned(),
+            rust_date_range.get("start").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+          date_range_map.insert(
+            "end_date".to_owned(),
+            rust_date_range.get("end").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+
+          string_to_c_str(ffi_response(
+            sample(&rust_db_name, &rust_table_name, Some(date_range_map), n as usize),
+            "Error sampling Parquet files",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_deleteTable(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
+  pub extern "C" fn Java_com_rustexample_TimonModule_countRows(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    date_range_json: *const c_char,
+  ) -> *mut c_char {
     unsafe {
-      match (c_str_to_string(db_name), c_str_to_string(table_name)) {
-        (Ok(rust_db_name), Ok(rust_table_name)) => match delete_table(&rust_db_name, &rust_table_name) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to delete table: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        (Err(e), _) | (_, Err(e)) => {
-          let err_message = serde_json::json!({ "error": e }).to_string();
-          string_to_c_str(err_message)
+      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(date_range_json)) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_date_range_json)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert(
+            "start_date".to_owned(),
+            rust_date_range.get("start").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+          date_range_map.insert(
+            "end_date".to_owned(),
+            rust_date_range.get("end").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+
+          string_to_c_str(ffi_response(
+            Runtime::new()
+              .unwrap()
+              .block_on(count_rows(&rust_db_name, &rust_table_name, Some(date_range_map))),
+            "Error counting rows",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_insert(
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryInto(
     db_name: *const c_char,
-    table_name: *const c_char,
-    json_data: *const c_char,
+    sql_query: *const c_char,
+    date_range_json: *const c_char,
+    dest_db_name: *const c_char,
+    dest_table_name: *const c_char,
+    options: *const c_char,
   ) -> *mut c_char {
     unsafe {
-      match (c_str_to_string(db_name), c_str_to_string(table_name), c_str_to_string(json_data)) {
-        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_json_data)) => match insert(&rust_db_name, &rust_table_name, &rust_json_data) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Error writing JSON data to Parquet file: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        _ => {
-          let err_message = serde_json::json!({ "error": "Invalid arguments" }).to_string();
-          string_to_c_str(err_message)
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(sql_query),
+        c_str_to_string(date_range_json),
+        c_str_to_string(dest_db_name),
+        c_str_to_string(dest_table_name),
+        c_str_to_string(options),
+      ) {
+        (Ok(rust_db_name), Ok(rust_sql_query), Ok(rust_date_range_json), Ok(rust_dest_db_name), Ok(rust_dest_table_name), Ok(rust_options)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert(
+            "start_date".to_owned(),
+            rust_date_range.get("start").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+          date_range_map.insert(
+            "end_date".to_owned(),
+            rust_date_range.get("end").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+
+          string_to_c_str(ffi_response(
+            Runtime::new().unwrap().block_on(query_into(
+              &rust_db_name,
+              &rust_sql_query,
+              Some(date_range_map),
+              &rust_dest_db_name,
+              &rust_dest_table_name,
+              &rust_options,
+            )),
+            "Error writing the query result to a table",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_query(
+  pub extern "C" fn Java_com_rustexample_TimonModule_createTableAs(
     db_name: *const c_char,
-    sql_query: *const c_char,
-    date_range_json: *const c_char, // TODO: Utilize date_range to optionally enhance the query by limiting the parquet files included based on the specified date range.
+    table_name: *const c_char,
+    date_range_json: *const c_char,
+    select_sql: *const c_char,
   ) -> *mut c_char {
     unsafe {
-      match (c_str_to_string(db_name), c_str_to_string(sql_query), c_str_to_string(date_range_json)) {
-        (Ok(rust_db_name), Ok(rust_date_range_json), Ok(rust_sql_query)) => {
-          match Runtime::new().unwrap().block_on(query(&rust_db_name, &rust_sql_query, None)) {
-            Ok(result) => {
-              let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "[]".to_string());
-              string_to_c_str(json_string)
-            }
-            Err(err) => {
-              let err_message = serde_json::json!({ "error": format!("Error querying Parquet files: {:?}", err) }).to_string();
-              string_to_c_str(err_message)
-            }
-          }
-        }
-        _ => {
-          let err_message = serde_json::json!({ "error": "Invalid arguments" }).to_string();
-          string_to_c_str(err_message)
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(table_name),
+        c_str_to_string(date_range_json),
+        c_str_to_string(select_sql),
+      ) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_date_range_json), Ok(rust_select_sql)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert(
+            "start_date".to_owned(),
+            rust_date_range.get("start").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+          date_range_map.insert(
+            "end_date".to_owned(),
+            rust_date_range.get("end").cloned().unwrap_or_else(|| "*".to_owned()),
+          );
+
+          string_to_c_str(ffi_response(
+            Runtime::new()
+              .unwrap()
+              .block_on(create_table_as(&rust_db_name, &rust_table_name, Some(date_range_map), &rust_select_sql)),
+            "Error creating the table from the query",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
@@ -574,31 +1680,80 @@ pub mod ios {
         c_str_to_string(access_key_id),
         c_str_to_string(secret_access_key),
       ) {
-        (Ok(rust_bucket_endpoint), Ok(rust_bucket_name), Ok(rust_access_key_id), Ok(rust_secret_access_key)) => {
-          match init_bucket(&rust_bucket_endpoint, &rust_bucket_name, &rust_access_key_id, &rust_secret_access_key) {
-            Ok(result) => {
-              let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-              string_to_c_str(json_string)
-            }
-            Err(err) => {
-              let err_message = serde_json::json!({ "error": format!("Failed to initialize S3 bucket: {:?}", err) }).to_string();
-              string_to_c_str(err_message)
-            }
-          }
-        }
-        _ => {
-          let err_message = serde_json::json!({ "error": "Invalid arguments" }).to_string();
-          string_to_c_str(err_message)
+        (Ok(rust_bucket_endpoint), Ok(rust_bucket_name), Ok(rust_access_key_id), Ok(rust_secret_access_key)) => string_to_c_str(ffi_response(
+          init_bucket(&rust_bucket_endpoint, &rust_bucket_name, &rust_access_key_id, &rust_secret_access_key),
+          "Failed to initialize S3 bucket",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_initBucketWithConfig(
+    bucket_endpoint: *const c_char,
+    bucket_name: *const c_char,
+    access_key_id: *const c_char,
+    secret_access_key: *const c_char,
+    client_config: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(bucket_endpoint),
+        c_str_to_string(bucket_name),
+        c_str_to_string(access_key_id),
+        c_str_to_string(secret_access_key),
+        c_str_to_string(client_config),
+      ) {
+        (Ok(rust_bucket_endpoint), Ok(rust_bucket_name), Ok(rust_access_key_id), Ok(rust_secret_access_key), Ok(rust_client_config)) => {
+          string_to_c_str(ffi_response(
+            init_bucket_with_config(
+              &rust_bucket_endpoint,
+              &rust_bucket_name,
+              &rust_access_key_id,
+              &rust_secret_access_key,
+              &rust_client_config,
+            ),
+            "Failed to initialize S3 bucket",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_reinitBucket(
+    bucket_endpoint: *const c_char,
+    bucket_name: *const c_char,
+    access_key_id: *const c_char,
+    secret_access_key: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(bucket_endpoint),
+        c_str_to_string(bucket_name),
+        c_str_to_string(access_key_id),
+        c_str_to_string(secret_access_key),
+      ) {
+        (Ok(rust_bucket_endpoint), Ok(rust_bucket_name), Ok(rust_access_key_id), Ok(rust_secret_access_key)) => string_to_c_str(ffi_response(
+          reinit_bucket(&rust_bucket_endpoint, &rust_bucket_name, &rust_access_key_id, &rust_secret_access_key),
+          "Failed to reinitialize S3 bucket",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
 
   #[no_mangle]
-  pub extern "C" fn Java_com_rustexample_TimonModule_queryBucket(date_range_json: *const c_char, sql_query: *const c_char) -> *mut c_char {
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryBucket(
+    date_range_json: *const c_char,
+    sql_query: *const c_char,
+    format: *const c_char,
+  ) -> *mut c_char {
     unsafe {
-      match (c_str_to_string(date_range_json), c_str_to_string(sql_query)) {
-        (Ok(rust_date_range_json), Ok(rust_sql_query)) => {
+      match (c_str_to_string(date_range_json), c_str_to_string(sql_query), c_str_to_string(format)) {
+        (Ok(rust_date_range_json), Ok(rust_sql_query), Ok(rust_format)) => {
           // Parse date_range_json into HashMap
           let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
           let start_date = rust_date_range.get("start").cloned().unwrap_or_else(|| "1970-01-01".to_string());
@@ -608,21 +1763,139 @@ pub mod ios {
           date_range_map.insert("start_date", start_date.as_str());
           date_range_map.insert("end_date", end_date.as_str());
 
-          match Runtime::new().unwrap().block_on(query_bucket(date_range_map, &rust_sql_query)) {
-            Ok(result) => {
-              let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "[]".to_string());
-              string_to_c_str(json_string)
-            }
-            Err(err) => {
-              let err_message = serde_json::json!({ "error": format!("Error querying bucket: {:?}", err) }).to_string();
-              string_to_c_str(err_message)
-            }
-          }
+          string_to_c_str(ffi_response(
+            Runtime::new()
+              .unwrap()
+              .block_on(query_bucket_with_format(date_range_map, &rust_sql_query, &rust_format)),
+            "Error querying bucket",
+          ))
         }
-        _ => {
-          let err_message = serde_json::json!({ "error": "Invalid arguments" }).to_string();
-          string_to_c_str(err_message)
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryBucketWithReadOptions(
+    date_range_json: *const c_char,
+    sql_query: *const c_char,
+    format: *const c_char,
+    read_options_json: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(date_range_json),
+        c_str_to_string(sql_query),
+        c_str_to_string(format),
+        c_str_to_string(read_options_json),
+      ) {
+        (Ok(rust_date_range_json), Ok(rust_sql_query), Ok(rust_format), Ok(rust_read_options_json)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let start_date = rust_date_range.get("start").cloned().unwrap_or_else(|| "1970-01-01".to_string());
+          let end_date = rust_date_range.get("end").cloned().unwrap_or_else(|| "1970-01-02".to_string());
+
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert("start_date", start_date.as_str());
+          date_range_map.insert("end_date", end_date.as_str());
+
+          string_to_c_str(ffi_response(
+            Runtime::new().unwrap().block_on(query_bucket_with_read_options(
+              date_range_map,
+              &rust_sql_query,
+              &rust_format,
+              &rust_read_options_json,
+            )),
+            "Error querying bucket",
+          ))
+        }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_queryHybrid(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    date_range_json: *const c_char,
+    sql_query: *const c_char,
+    local_retention_days: u32,
+    format: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(table_name),
+        c_str_to_string(date_range_json),
+        c_str_to_string(sql_query),
+        c_str_to_string(format),
+      ) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_date_range_json), Ok(rust_sql_query), Ok(rust_format)) => {
+          let rust_date_range: HashMap<String, String> = serde_json::from_str(&rust_date_range_json).unwrap_or_default();
+          let start_date = rust_date_range.get("start").cloned().unwrap_or_else(|| "*".to_string());
+          let end_date = rust_date_range.get("end").cloned().unwrap_or_else(|| "*".to_string());
+
+          let mut date_range_map = HashMap::new();
+          date_range_map.insert("start_date", start_date.as_str());
+          date_range_map.insert("end_date", end_date.as_str());
+
+          string_to_c_str(ffi_response(
+            Runtime::new().unwrap().block_on(query_hybrid(
+              &rust_db_name,
+              &rust_table_name,
+              date_range_map,
+              &rust_sql_query,
+              local_retention_days as u64,
+              &rust_format,
+            )),
+            "Error querying local and bucket Parquet files",
+          ))
         }
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_availableDatesBucket(table_name: *const c_char) -> *mut c_char {
+    unsafe {
+      match c_str_to_string(table_name) {
+        Ok(rust_table_name) => string_to_c_str(ffi_response(
+          Runtime::new().unwrap().block_on(available_dates_bucket(&rust_table_name)),
+          "Failed to list available dates in bucket",
+        )),
+        Err(e) => string_to_c_str(ffi_error(&e)),
+      }
+    }
+  }
+
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_sinkRange(
+    db_name: *const c_char,
+    table_name: *const c_char,
+    start_date: *const c_char,
+    end_date: *const c_char,
+    granularity: *const c_char,
+  ) -> *mut c_char {
+    unsafe {
+      match (
+        c_str_to_string(db_name),
+        c_str_to_string(table_name),
+        c_str_to_string(start_date),
+        c_str_to_string(end_date),
+        c_str_to_string(granularity),
+      ) {
+        (Ok(rust_db_name), Ok(rust_table_name), Ok(rust_start_date), Ok(rust_end_date), Ok(rust_granularity)) => string_to_c_str(ffi_response(
+          Runtime::new().unwrap().block_on(sink_range(
+            &rust_db_name,
+            &rust_table_name,
+            &rust_start_date,
+            &rust_end_date,
+            &rust_granularity,
+          )),
+          "Failed to sink the date range",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
@@ -631,21 +1904,29 @@ pub mod ios {
   pub extern "C" fn Java_com_rustexample_TimonModule_sinkMonthlyParquet(db_name: *const c_char, table_name: *const c_char) -> *mut c_char {
     unsafe {
       match (c_str_to_string(db_name), c_str_to_string(table_name)) {
-        (Ok(rust_db_name), Ok(rust_table_name)) => match Runtime::new().unwrap().block_on(sink_daily_parquet(&rust_db_name, &rust_table_name)) {
-          Ok(result) => {
-            let json_string = serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string());
-            string_to_c_str(json_string)
-          }
-          Err(err) => {
-            let err_message = serde_json::json!({ "error": format!("Failed to sink monthly Parquet files: {:?}", err) }).to_string();
-            string_to_c_str(err_message)
-          }
-        },
-        _ => {
-          let err_message = serde_json::json!({ "error": "Invalid arguments" }).to_string();
-          string_to_c_str(err_message)
-        }
+        (Ok(rust_db_name), Ok(rust_table_name)) => string_to_c_str(ffi_response(
+          Runtime::new().unwrap().block_on(sink_daily_parquet(&rust_db_name, &rust_table_name)),
+          "Failed to sink monthly Parquet files",
+        )),
+        _ => string_to_c_str(ffi_error("Invalid arguments")),
       }
     }
   }
+
+  // Register a function called with the JSON of each `SyncProgress` during a sink, or remove it with NULL. The string
+  // is only valid during the call; the function may run on any thread.
+  #[no_mangle]
+  pub extern "C" fn Java_com_rustexample_TimonModule_setSyncProgressCallback(callback: Option<extern "C" fn(*const c_char)>) -> *mut c_char {
+    let callback: Option<ProgressCallback> = callback.map(|callback| {
+      Arc::new(move |progress: &SyncProgress| {
+        if let Ok(progress_json) = CString::new(serde_json::to_string(progress).unwrap_or_default()) {
+          callback(progress_json.as_ptr());
+        }
+      }) as ProgressCallback
+    });
+    string_to_c_str(ffi_response(
+      set_sync_progress_callback(callback),
+      "Failed to set the sync progress callback",
+    ))
+  }
 }
@@ -7,7 +7,10 @@ mod cli;
 #[cfg(feature = "dev_cli")]
 use clap::Parser;
 #[cfg(feature = "dev_cli")]
-use cli::{convert_json_to_parquet, execute_query, Commands, CLI};
+use cli::{
+  convert_json_to_parquet, execute_db_query, execute_query, explain_db_query, fetch_table, print_storage_info, print_table_schema, restore_db,
+  sink_table, Commands, CLI,
+};
 
 #[allow(dead_code)]
 async fn test_local_storage() {
@@ -29,7 +32,7 @@ async fn test_local_storage() {
       "ring_details": { "type": "array", "required": true }
     }
   "#;
-  let table_result = create_table(DATABASE_NAME, "temperature", &table_schema);
+  let table_result = create_table(DATABASE_NAME, "temperature", table_schema);
   println!("create_table -> {}", table_result.unwrap());
 
   let databases_list = list_databases().unwrap();
@@ -62,7 +65,7 @@ async fn test_local_storage() {
   println!("insertion_result: {}", insertion_result.unwrap());
 
   // let range: std::collections::HashMap<&str, &str> = std::collections::HashMap::from([("start_date", "2024-12-12"), ("end_date", "2025-01-12")]);
-  let sql_query = format!("SELECT * FROM temperature ORDER BY date ASC LIMIT 25");
+  let sql_query = "SELECT * FROM temperature ORDER BY date ASC LIMIT 25".to_string();
   let query_result = query(DATABASE_NAME, &sql_query, None).await;
   println!("query_result: {}", query_result.unwrap());
 
@@ -85,7 +88,7 @@ async fn test_s3_sync() {
 
   let range = std::collections::HashMap::from([("start_date", "2024-07-01"), ("end_date", "2024-08-01")]);
   let sql_query = "SELECT * FROM temperature LIMIT 25";
-  let df_result = query_bucket(range, &sql_query).await.unwrap();
+  let df_result = query_bucket(range, sql_query).await.unwrap();
   println!("query_bucket {:?}", df_result);
 
   let sink_daily_parquet_result = sink_daily_parquet("test", "temperature").await;
@@ -106,13 +109,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
   let cli = CLI::parse();
 
   match &cli.command {
-    Commands::Convert { input, output } => {
-      convert_json_to_parquet(input.as_str(), output.as_str())?;
+    Commands::Convert { input, output, compression } => {
+      convert_json_to_parquet(input.as_str(), output.as_str(), compression.as_str())?;
       println!("JSON converted to Parquet successfully.");
     }
     Commands::Query { file, query } => {
       execute_query(file.as_str(), query.as_str()).await?;
     }
+    Commands::Db {
+      storage_path,
+      db,
+      table,
+      start,
+      end,
+      query,
+    } => {
+      execute_db_query(storage_path, db, table, start.as_deref(), end.as_deref(), query.as_deref()).await?;
+    }
+    Commands::Explain {
+      storage_path,
+      db,
+      query,
+      start,
+      end,
+      analyze,
+    } => {
+      explain_db_query(storage_path, db, query, start.as_deref(), end.as_deref(), *analyze).await?;
+    }
+    Commands::Info { storage_path } => {
+      print_storage_info(storage_path)?;
+    }
+    Commands::Schema { storage_path, db, table } => {
+      print_table_schema(storage_path, db, table)?;
+    }
+    Commands::Sink {
+      storage_path,
+      db,
+      table,
+      bucket,
+      start,
+      end,
+      granularity,
+    } => {
+      sink_table(storage_path, db, table, bucket, start.as_deref(), end.as_deref(), granularity.as_deref()).await?;
+    }
+    Commands::Fetch {
+      storage_path,
+      table,
+      bucket,
+      start,
+      end,
+      query,
+    } => {
+      fetch_table(storage_path, table, bucket, start, end, query.as_deref()).await?;
+    }
+    Commands::Restore {
+      storage_path,
+      db,
+      bucket,
+      mode,
+    } => {
+      restore_db(storage_path, db, bucket, mode).await?;
+    }
   }
   Ok(())
 }
@@ -1,28 +1,182 @@
 use crate::timon_engine::helpers;
-use datafusion::datasource::listing::{ListingTable, ListingTableConfig, ListingTableUrl};
+use chrono::{Datelike, Days, NaiveDate, Utc};
+use datafusion::dataframe::DataFrame;
 use datafusion::datasource::MemTable;
-use datafusion::error::Result as DataFusionResult;
-use datafusion::prelude::*;
-use helpers::{generate_paths, record_batches_to_json, Granularity};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use futures::TryStreamExt;
+use helpers::{
+  arrow_schema_to_table_schema, collect_with_limits, get_unique_fields, is_within_bounds, list_monthly_files, list_partition_files, month_overlaps,
+  months_in_range, parse_date_bounds, partition_label, read_parquet_arrow_schema, record_batches_to_json, register_queried_table,
+  resolve_relative_date_range, select_bucket_sources, union_all_by_name, validate_read_only_sql,
+};
 use object_store::{
   aws::{AmazonS3, AmazonS3Builder},
   path::Path as StorePath,
-  ObjectStore,
+  ClientOptions, ObjectStore, RetryConfig,
 };
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncReadExt;
 use url::Url;
 
-use super::db_manager::{DataFusionOutput, DatabaseManager};
+use super::db_manager::{DataFusionOutput, DatabaseManager, NoDataInRange, ReadOptions, TableNotFound};
 use super::helpers::extract_table_name;
+use super::stats;
+
+// A tier of `query_hybrid` without any file (or table) in its part of the range reads as no rows; the tiers missing
+// the table are counted so a table found in neither answers `TableNotFound`
+fn skip_missing_data(output: DataFusionResult<DataFusionOutput>, tiers_without_table: &mut usize) -> DataFusionResult<Option<DataFusionOutput>> {
+  match output {
+    Ok(output) => Ok(Some(output)),
+    Err(DataFusionError::Plan(message)) if message == "No valid tables found to query." => Ok(None),
+    Err(error) if NoDataInRange::from_error(&error).is_some() => Ok(None),
+    Err(error) if TableNotFound::from_error(&error).is_some() => {
+      *tiers_without_table += 1;
+      Ok(None)
+    }
+    Err(error) => Err(error),
+  }
+}
+
+// Most objects a bucket query lists when `QueryConfig.max_bucket_objects` is unset
+const DEFAULT_MAX_BUCKET_OBJECTS: usize = 10_000;
+
+// Outcome of a sink: bucket paths uploaded or skipped (already synced) and the local files that failed
+#[derive(Serialize, Debug, Default)]
+pub struct SinkSummary {
+  pub uploaded: Vec<String>,
+  pub skipped: Vec<String>,
+  pub failed: Vec<SinkFailure>,
+  pub bytes_uploaded: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SinkFailure {
+  pub file: String,
+  pub error: String,
+}
+
+// What `restore` does when the database already exists locally
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RestoreMode {
+  #[default]
+  Error, // Refuse to restore, leaving the local database untouched
+  Merge,     // Download only the files missing locally; local files and table schemas win
+  Overwrite, // Delete the local database, then restore it from the bucket
+}
+
+impl RestoreMode {
+  pub fn parse(name: &str) -> Result<Self, String> {
+    match name.trim().to_lowercase().as_str() {
+      "" | "error" => Ok(RestoreMode::Error),
+      "merge" => Ok(RestoreMode::Merge),
+      "overwrite" => Ok(RestoreMode::Overwrite),
+      other => Err(format!("Unsupported restore mode '{}', expected 'error', 'merge' or 'overwrite'", other)),
+    }
+  }
+}
+
+// Which files of a table `sink_range` uploads
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SinkGranularity {
+  #[default]
+  Daily, // Daily files and their append segments, to their `BucketLayout` key
+  Monthly, // Monthly files, to the bucket root
+}
+
+impl SinkGranularity {
+  pub fn parse(name: &str) -> Result<Self, String> {
+    match name.trim().to_lowercase().as_str() {
+      "" | "daily" => Ok(SinkGranularity::Daily),
+      "monthly" => Ok(SinkGranularity::Monthly),
+      other => Err(format!("Unsupported sink granularity '{}', expected 'daily' or 'monthly'", other)),
+    }
+  }
+}
+
+// Progress of a sink or restore, reported once per file whether it was transferred, skipped or failed
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncProgress {
+  pub operation: &'static str, // "sink" or "restore"
+  pub file: String,            // Local path of the file just handled
+  pub files_done: usize,
+  pub files_total: usize,
+  pub bytes_transferred: u64, // Bytes uploaded (or downloaded) so far
+}
+
+// Called with each `SyncProgress`, e.g. to drive a progress bar during the initial full sync
+pub type ProgressCallback = Arc<dyn Fn(&SyncProgress) + Send + Sync>;
+
+// A panicking callback (e.g. a UI that went away) is ignored so it can't abort the sync
+fn report_progress(progress: Option<&ProgressCallback>, update: SyncProgress) {
+  if let Some(callback) = progress {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(&update)));
+  }
+}
+
+// Outcome of a restore: tables added to the metadata, files downloaded and files kept because they existed locally
+#[derive(Serialize, Debug, Default)]
+pub struct RestoreSummary {
+  pub tables: Vec<String>,
+  pub downloaded: Vec<String>,
+  pub skipped: Vec<String>,
+}
+
+// Where sinks put a table's daily files (and their append segments) in the bucket, keeping the local file name
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketLayout {
+  #[default]
+  Flat, // `{db}/{YYYY}/{MM}/{table}_{YYYY-MM-DD}[_n].parquet`
+  // `{db}/{table}/date={YYYY-MM-DD}/{table}_{YYYY-MM-DD}[_n].parquet`: one Hive partition directory per day, so Spark or
+  // Athena read `{db}/{table}/` as a table partitioned by `date`
+  Hive,
+}
+
+impl BucketLayout {
+  pub fn daily_key(&self, db_name: &str, table_name: &str, date: NaiveDate, file_name: &str) -> String {
+    match self {
+      BucketLayout::Flat => format!("{}/{}/{}", db_name, date.format("%Y/%m"), file_name),
+      BucketLayout::Hive => format!("{}/{}/date={}/{}", db_name, table_name, date.format("%Y-%m-%d"), file_name),
+    }
+  }
+}
+
+// Timeouts and retries of the bucket's HTTP client, so a stalled mobile connection fails instead of hanging a call.
+// A request is retried with backoff until `max_retries` retries or `retry_timeout_secs` have passed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BucketClientConfig {
+  pub connect_timeout_secs: u64, // Time to establish a connection
+  pub request_timeout_secs: u64, // Time for a whole request, from connecting to reading the last byte of the response
+  pub max_retries: usize,        // Retries of a failed request, 0 to disable them
+  pub retry_timeout_secs: u64,   // Time after the first attempt past which a request is no longer retried
+  pub layout: BucketLayout,      // Keys the sinks write daily files to; queries and restores read both layouts
+}
+
+impl Default for BucketClientConfig {
+  fn default() -> Self {
+    BucketClientConfig {
+      connect_timeout_secs: 5,
+      request_timeout_secs: 30,
+      max_retries: 3,
+      retry_timeout_secs: 60,
+      layout: BucketLayout::Flat,
+    }
+  }
+}
 
 pub struct CloudStorageManager {
   s3_store: Arc<AmazonS3>,
   db_manager: DatabaseManager,
   pub bucket_name: String,
+  pub client_config: BucketClientConfig,
 }
 
 impl CloudStorageManager {
@@ -33,6 +187,24 @@ impl CloudStorageManager {
     access_key_id: Option<&str>,
     secret_access_key: Option<&str>,
     bucket_name: Option<&str>,
+  ) -> Self {
+    Self::new_with_config(
+      db_manager,
+      bucket_endpoint,
+      access_key_id,
+      secret_access_key,
+      bucket_name,
+      BucketClientConfig::default(),
+    )
+  }
+
+  pub fn new_with_config(
+    db_manager: DatabaseManager,
+    bucket_endpoint: Option<&str>,
+    access_key_id: Option<&str>,
+    secret_access_key: Option<&str>,
+    bucket_name: Option<&str>,
+    client_config: BucketClientConfig,
   ) -> Self {
     let bucket_endpoint = bucket_endpoint.unwrap_or("http://localhost:9000").to_owned();
     let bucket_name = bucket_name.unwrap_or("timon").to_owned();
@@ -44,7 +216,18 @@ impl CloudStorageManager {
       .with_bucket_name(&bucket_name)
       .with_access_key_id(&access_key_id)
       .with_secret_access_key(&secret_access_key)
-      .with_allow_http(true)
+      // The client options replace the builder's, so plain-HTTP endpoints (local MinIO) are allowed on them
+      .with_client_options(
+        ClientOptions::new()
+          .with_allow_http(true)
+          .with_connect_timeout(Duration::from_secs(client_config.connect_timeout_secs))
+          .with_timeout(Duration::from_secs(client_config.request_timeout_secs)),
+      )
+      .with_retry(RetryConfig {
+        max_retries: client_config.max_retries,
+        retry_timeout: Duration::from_secs(client_config.retry_timeout_secs),
+        ..RetryConfig::default()
+      })
       .build()
       .unwrap();
 
@@ -52,72 +235,616 @@ impl CloudStorageManager {
       s3_store: Arc::new(s3_store),
       db_manager,
       bucket_name,
+      client_config,
     }
   }
 
   #[allow(dead_code)]
-  pub async fn query_bucket(&self, date_range: HashMap<String, String>, sql_query: &str, is_json_format: bool) -> DataFusionResult<DataFusionOutput> {
-    let session_context = SessionContext::new();
+  pub async fn query_bucket(
+    &self,
+    date_range: HashMap<String, String>,
+    sql_query: &str,
+    is_json_format: bool,
+    read_options: &ReadOptions,
+  ) -> DataFusionResult<DataFusionOutput> {
+    self.query_bucket_in(None, date_range, sql_query, is_json_format, read_options).await
+  }
+
+  // Same as `query_bucket`, reading only the daily objects under the `{db_name}/` prefix when a database is given.
+  // Monthly objects sit at the bucket root without a database in their key, so they're read either way.
+  async fn query_bucket_in(
+    &self,
+    db_name: Option<&str>,
+    date_range: HashMap<String, String>,
+    sql_query: &str,
+    is_json_format: bool,
+    read_options: &ReadOptions,
+  ) -> DataFusionResult<DataFusionOutput> {
+    let started = Instant::now();
+    let output = self.run_bucket_query(db_name, date_range, sql_query, is_json_format, read_options).await;
+    stats::record_query(started, output.is_ok());
+    output
+  }
+
+  async fn run_bucket_query(
+    &self,
+    db_name: Option<&str>,
+    date_range: HashMap<String, String>,
+    sql_query: &str,
+    is_json_format: bool,
+    read_options: &ReadOptions,
+  ) -> DataFusionResult<DataFusionOutput> {
+    validate_read_only_sql(sql_query)?;
+    let session_context = self.db_manager.session_context();
     let file_name = &extract_table_name(sql_query);
 
-    // Parse the date_range and generate Parquet file paths
-    let file_list = generate_paths(&self.bucket_name, file_name, date_range, Granularity::Month, true).unwrap();
+    // Resolve relative ranges (e.g. `{"last": "7d"}`), then parse the date_range bounds
+    let date_range = resolve_relative_date_range(&date_range).map_err(|e| DataFusionError::Plan(format!("Invalid date range: {}", e)))?;
+    let (start_date, end_date) = parse_date_bounds(Some(&date_range)).map_err(|e| DataFusionError::Plan(format!("Invalid date range: {}", e)))?;
     // Register the object store with the session context
     let store_url = Url::parse(&format!("s3://{}", &self.bucket_name)).unwrap();
     session_context.runtime_env().register_object_store(&store_url, self.s3_store.clone());
 
+    // Read each month from its monthly object when it was archived, from its daily objects otherwise; a table without
+    // any object under the listed prefixes isn't in the bucket for that range
+    let (object_keys, listed_prefixes) = self.list_table_objects(db_name, file_name, start_date, end_date).await?;
+    let table_objects = select_bucket_sources(&object_keys, file_name, None, None);
+    if table_objects.is_empty() {
+      let error = TableNotFound {
+        table_name: file_name.to_owned(),
+        location: if listed_prefixes.is_empty() {
+          format!("bucket '{}'", self.bucket_name)
+        } else {
+          format!("bucket '{}' (listed the root and {})", self.bucket_name, listed_prefixes.join(", "))
+        },
+      };
+      return Err(error.into_datafusion_error());
+    }
+    let file_list = select_bucket_sources(&table_objects, file_name, start_date, end_date);
+    if file_list.is_empty() {
+      let error = NoDataInRange {
+        table_name: file_name.to_owned(),
+        location: format!("bucket '{}'", self.bucket_name),
+        start_date,
+        end_date,
+      };
+      return Err(error.into_datafusion_error());
+    }
+    self.db_manager.check_file_count(file_list.len(), file_name)?;
+    let file_list = file_list
+      .into_iter()
+      .filter(|key| read_options.reads(key))
+      .map(|key| format!("s3://{}/{}", self.bucket_name, key));
+    let read_schema = read_options.arrow_schema().map_err(DataFusionError::Plan)?;
+
     // Create a list of table names and register Parquet files
     let mut table_names = Vec::new();
-    for (i, file_url) in file_list.iter().enumerate() {
-      let table_name = format!("{}_{}", file_name, i);
-      let file_url_parsed = ListingTableUrl::parse(file_url)?;
-
-      let mut config = ListingTableConfig::new(file_url_parsed);
-      config = config.infer(&session_context.state()).await?;
-
-      let table = ListingTable::try_new(config)?;
-      session_context.register_table(&table_name, Arc::new(table))?;
+    for (i, file_url) in file_list.enumerate() {
+      let table_name = format!("{}_{}", file_name.to_lowercase(), i);
+      session_context
+        .register_parquet(&table_name, &file_url, read_options.parquet_read_options(read_schema.as_ref()))
+        .await?;
+      stats::record_bucket_file_read();
       table_names.push(table_name);
     }
 
     if table_names.is_empty() {
-      return Err(datafusion::error::DataFusionError::Plan("No valid tables found to query.".to_string()));
+      return Err(DataFusionError::Plan("No valid tables found to query.".to_string()));
     }
 
     // Combine all tables into a single SQL query using UNION ALL
     let combined_query = format!(
       "SELECT * FROM ({}) AS combined_table",
-      table_names
-        .iter()
-        .map(|name| format!("SELECT * FROM {}", name))
-        .collect::<Vec<_>>()
-        .join(" UNION ALL ")
+      union_all_by_name(&session_context, &table_names, false).await?
     );
 
-    // Execute the combined query
+    // Register the combined query as a view under the queried table name so the user-provided SQL runs unchanged,
+    // streaming from the objects into a result held to the limits
     let combined_df = session_context.sql(&combined_query).await?;
-    let combined_results = combined_df.collect().await?;
-    // Create an in-memory table from the combined results
-    let schema = combined_results[0].schema();
-    let mem_table = MemTable::try_new(schema, vec![combined_results])?;
-    session_context.register_table("combined_table", Arc::new(mem_table))?;
-    // Adjust the user-provided SQL query to run on the combined table
-    let adjusted_sql_query = sql_query.replace(file_name, "combined_table");
-    // Execute the user-provided SQL query on the combined table
-    let final_df = session_context.sql(&adjusted_sql_query).await?;
-    let final_results = final_df.collect().await?;
+    register_queried_table(&session_context, file_name, combined_df.into_view())?;
+    let final_df = session_context.sql(sql_query).await?;
+    let final_schema = final_df.schema().inner().clone();
+    let query_config = &self.db_manager.query_config;
+    let final_results = collect_with_limits(final_df, query_config.max_rows, query_config.max_bytes).await?;
 
     if is_json_format {
-      let json_result = record_batches_to_json(&final_results).unwrap();
+      let json_result = record_batches_to_json(&final_results, self.db_manager.query_config.non_finite_floats).unwrap();
       Ok(DataFusionOutput::Json(json_result))
     } else {
-      let final_schema = final_results[0].schema();
+      // The plan's schema stands in for the batches' when the result has no rows
+      let final_schema = final_results.first().map_or(final_schema, |batch| batch.schema());
       let final_mem_table = MemTable::try_new(final_schema, vec![final_results])?;
       let final_df = session_context.read_table(Arc::new(final_mem_table))?;
       Ok(DataFusionOutput::DataFrame(final_df))
     }
   }
 
+  // The partitions of `file_name` in the bucket, sorted like `DatabaseManager::available_dates`: `YYYY-MM-DD` for each
+  // day with a daily object, `YYYY-MM` for each month read from a monthly object
+  #[allow(dead_code)]
+  pub async fn available_dates(&self, file_name: &str) -> DataFusionResult<Vec<String>> {
+    let (object_keys, _) = self.list_table_objects(None, file_name, None, None).await?;
+    let table_objects = select_bucket_sources(&object_keys, file_name, None, None);
+    if table_objects.is_empty() {
+      let error = TableNotFound {
+        table_name: file_name.to_owned(),
+        location: format!("bucket '{}'", self.bucket_name),
+      };
+      return Err(error.into_datafusion_error());
+    }
+    let dates: BTreeSet<String> = table_objects.iter().filter_map(|key| partition_label(key, file_name)).collect();
+    Ok(dates.into_iter().collect())
+  }
+
+  // List the objects that may hold `file_name` rows within the date range without listing the whole bucket: the root
+  // (monthly objects and one prefix per database), then the `{db}/{YYYY}/{MM}` prefix of every month in range that has
+  // no monthly object, and with the Hive layout the `{db}/{table}` prefix from the range's first day. Returns the object
+  // keys and the prefixes listed; fails past `max_bucket_objects` objects. With `db_name`, only that database's prefix is
+  // listed after the root.
+  async fn list_table_objects(
+    &self,
+    db_name: Option<&str>,
+    file_name: &str,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+  ) -> DataFusionResult<(Vec<String>, Vec<String>)> {
+    let max_objects = self.db_manager.query_config.max_bucket_objects.unwrap_or(DEFAULT_MAX_BUCKET_OBJECTS);
+    let check_count = |count: usize| {
+      if count > max_objects {
+        return Err(DataFusionError::ResourcesExhausted(format!(
+          "Bucket query listed more than {} objects, narrow the date range or raise max_bucket_objects",
+          max_objects
+        )));
+      }
+      Ok(())
+    };
+
+    let root = self
+      .s3_store
+      .list_with_delimiter(None)
+      .await
+      .map_err(|e| DataFusionError::External(Box::new(e)))?;
+    check_count(root.objects.len())?;
+    let mut object_keys: Vec<String> = root.objects.iter().map(|object| object.location.to_string()).collect();
+    let archived_months: HashSet<NaiveDate> = select_bucket_sources(&object_keys, file_name, None, None)
+      .iter()
+      .filter_map(|key| {
+        let month = key.strip_prefix(&format!("{}_", file_name))?.strip_suffix(".parquet")?;
+        NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()
+      })
+      .collect();
+
+    let mut listed_prefixes = Vec::new();
+    for db_prefix in root
+      .common_prefixes
+      .iter()
+      .filter(|prefix| db_name.is_none_or(|db_name| prefix.as_ref() == db_name))
+    {
+      for month_start in self.list_months(db_prefix, start_date, end_date).await? {
+        if archived_months.contains(&month_start) {
+          continue;
+        }
+        let prefix = StorePath::from(format!("{}/{}", db_prefix, month_start.format("%Y/%m")));
+        let mut objects = self.s3_store.list(Some(&prefix));
+        while let Some(object) = objects.try_next().await.map_err(|e| DataFusionError::External(Box::new(e)))? {
+          object_keys.push(object.location.to_string());
+          check_count(object_keys.len())?;
+        }
+        listed_prefixes.push(prefix.to_string());
+      }
+      if self.client_config.layout == BucketLayout::Hive {
+        // Partition directories sort by day, so the listing starts at the range's first one
+        let prefix = StorePath::from(format!("{}/{}", db_prefix, file_name));
+        let mut objects = match start_date {
+          Some(start) => self
+            .s3_store
+            .list_with_offset(Some(&prefix), &StorePath::from(format!("{}/date={}", prefix, start.format("%Y-%m-%d")))),
+          None => self.s3_store.list(Some(&prefix)),
+        };
+        while let Some(object) = objects.try_next().await.map_err(|e| DataFusionError::External(Box::new(e)))? {
+          object_keys.push(object.location.to_string());
+          check_count(object_keys.len())?;
+        }
+        listed_prefixes.push(prefix.to_string());
+      }
+    }
+    Ok((object_keys, listed_prefixes))
+  }
+
+  // The months in range under a database prefix: every month of a closed range, or the `{YYYY}/{MM}` folders found
+  // under the prefix when a side of the range is open
+  async fn list_months(&self, db_prefix: &StorePath, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> DataFusionResult<Vec<NaiveDate>> {
+    if let (Some(start), Some(end)) = (start_date, end_date) {
+      return Ok(months_in_range(start, end));
+    }
+
+    let mut months = Vec::new();
+    let years = self
+      .s3_store
+      .list_with_delimiter(Some(db_prefix))
+      .await
+      .map_err(|e| DataFusionError::External(Box::new(e)))?;
+    for year_prefix in years.common_prefixes {
+      let Some(year) = year_prefix.filename().and_then(|year| year.parse::<i32>().ok()) else {
+        continue;
+      };
+      let in_range = start_date.is_none_or(|start| year >= start.year()) && end_date.is_none_or(|end| year <= end.year());
+      if !in_range {
+        continue;
+      }
+      let month_prefixes = self
+        .s3_store
+        .list_with_delimiter(Some(&year_prefix))
+        .await
+        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+      months.extend(
+        month_prefixes
+          .common_prefixes
+          .iter()
+          .filter_map(|month_prefix| month_prefix.filename()?.parse::<u32>().ok())
+          .filter_map(|month| NaiveDate::from_ymd_opt(year, month, 1))
+          .filter(|month_start| month_overlaps(*month_start, start_date, end_date)),
+      );
+    }
+    Ok(months)
+  }
+
+  // Read a table's recent days from the local files and the older ones from the bucket, then run `sql_query` over both.
+  // Days from `local_retention_days` ago through today are local, the days before come from the bucket. A row whose
+  // unique fields match in both tiers is kept from the local files, which hold the latest corrections.
+  pub async fn query_hybrid(
+    &self,
+    db_manager: &DatabaseManager,
+    db_name: &str,
+    table_name: &str,
+    date_range: HashMap<String, String>,
+    sql_query: &str,
+    local_retention_days: u64,
+  ) -> DataFusionResult<DataFrame> {
+    validate_read_only_sql(sql_query)?;
+    let date_range = resolve_relative_date_range(&date_range).map_err(|e| DataFusionError::Plan(format!("Invalid date range: {}", e)))?;
+    let (start_date, end_date) = parse_date_bounds(Some(&date_range)).map_err(|e| DataFusionError::Plan(format!("Invalid date range: {}", e)))?;
+    let horizon = Utc::now().date_naive() - Days::new(local_retention_days);
+    let format_bound = |bound: Option<NaiveDate>| bound.map_or("*".to_owned(), |date| date.format("%Y-%m-%d").to_string());
+    let tier_range = |start: Option<NaiveDate>, end: Option<NaiveDate>| {
+      HashMap::from([("start_date".to_owned(), format_bound(start)), ("end_date".to_owned(), format_bound(end))])
+    };
+    let select_all = format!("SELECT * FROM \"{}\"", table_name);
+
+    // Each tier reads the rows of its part of the range; a part that is empty, or holds no file, adds no rows
+    let session_context = db_manager.session_context();
+    let mut table_names = Vec::new();
+    let (mut tiers_queried, mut tiers_without_table) = (0, 0);
+    let cloud_end = end_date.map_or(horizon.pred_opt(), |end| Some(end.min(horizon.pred_opt().unwrap_or(end))));
+    if start_date.zip(cloud_end).is_none_or(|(start, end)| start <= end) {
+      tiers_queried += 1;
+      if let Some(DataFusionOutput::DataFrame(df)) = skip_missing_data(
+        self
+          .query_bucket_in(
+            Some(db_name),
+            tier_range(start_date, cloud_end),
+            &select_all,
+            false,
+            &ReadOptions::default(),
+          )
+          .await,
+        &mut tiers_without_table,
+      )? {
+        let cloud_table = format!("{}_cloud", table_name.to_lowercase());
+        session_context.register_table(cloud_table.as_str(), df.into_view())?;
+        table_names.push(cloud_table);
+      }
+    }
+    let local_start = Some(start_date.map_or(horizon, |start| start.max(horizon)));
+    if local_start.zip(end_date).is_none_or(|(start, end)| start <= end) {
+      tiers_queried += 1;
+      let local_output = db_manager
+        .query(db_name, &select_all, Some(tier_range(local_start, end_date)), false)
+        .await;
+      if let Some(DataFusionOutput::DataFrame(df)) = skip_missing_data(local_output, &mut tiers_without_table)? {
+        let local_table = format!("{}_local", table_name.to_lowercase());
+        session_context.register_table(local_table.as_str(), df.into_view())?;
+        table_names.push(local_table);
+      }
+    }
+    if table_names.is_empty() && tiers_queried > 0 && tiers_without_table == tiers_queried {
+      let error = TableNotFound {
+        table_name: table_name.to_owned(),
+        location: format!("database '{}' or bucket '{}'", db_name, self.bucket_name),
+      };
+      return Err(error.into_datafusion_error());
+    }
+    if table_names.is_empty() {
+      let error = NoDataInRange {
+        table_name: table_name.to_owned(),
+        location: format!("database '{}' or bucket '{}'", db_name, self.bucket_name),
+        start_date,
+        end_date,
+      };
+      return Err(error.into_datafusion_error());
+    }
+
+    let unique_fields = db_manager
+      .get_table_schema(db_name, table_name)
+      .ok()
+      .and_then(|schema| get_unique_fields(schema).ok())
+      .unwrap_or_default();
+    let combined_query = if unique_fields.is_empty() || table_names.len() == 1 {
+      format!(
+        "SELECT * FROM ({}) AS combined_table",
+        union_all_by_name(&session_context, &table_names, false).await?
+      )
+    } else {
+      // Rank the rows of each key with the local tier (registered last) first and keep the first one
+      format!(
+        "SELECT * EXCLUDE (__timon_file_order, __timon_row_rank) FROM (\
+           SELECT *, ROW_NUMBER() OVER (PARTITION BY {} ORDER BY __timon_file_order DESC) AS __timon_row_rank FROM ({}) AS ordered_table\
+         ) AS combined_table WHERE __timon_row_rank = 1",
+        unique_fields.iter().map(|field| format!("\"{}\"", field)).collect::<Vec<_>>().join(", "),
+        union_all_by_name(&session_context, &table_names, true).await?
+      )
+    };
+    let combined_df = session_context.sql(&combined_query).await?;
+    register_queried_table(&session_context, table_name, combined_df.into_view())?;
+
+    let final_df = session_context.sql(sql_query).await?;
+    let final_schema = final_df.schema().inner().clone();
+    let query_config = &db_manager.query_config;
+    let final_results = collect_with_limits(final_df, query_config.max_rows, query_config.max_bytes).await?;
+    let final_schema = final_results.first().map_or(final_schema, |batch| batch.schema());
+    let final_mem_table = MemTable::try_new(final_schema, vec![final_results])?;
+    session_context.read_table(Arc::new(final_mem_table))
+  }
+
+  // Store any bytes under `key` (e.g. sync manifests or a copy of metadata.json), replacing an existing object
+  #[allow(dead_code)]
+  pub async fn put_object(&self, key: &str, bytes: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    let size = bytes.len();
+    self.s3_store.put(&StorePath::from(key), bytes.into()).await?;
+    stats::record_bucket_upload(size);
+    Ok(())
+  }
+
+  #[allow(dead_code)]
+  pub async fn get_object(&self, key: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let object = self.s3_store.get(&StorePath::from(key)).await?;
+    let bytes = object.bytes().await?.to_vec();
+    stats::record_bucket_download(bytes.len());
+    Ok(bytes)
+  }
+
+  // Rebuild a local database from the files `sink_daily_parquet` uploaded under `{db_name}/`. A table missing from the
+  // metadata is created with the schema snapshot at `{db_name}/_schemas/{table}.json` when one was uploaded (see
+  // `put_object`), otherwise with a schema inferred from one of its files. `RestoreMode::Overwrite` downloads into
+  // `staging_dir` and replaces the local database only once every file is down, so a failed restore keeps the old data.
+  #[allow(dead_code)]
+  pub async fn restore(
+    &self,
+    db_name: &str,
+    mode: RestoreMode,
+    progress: Option<&ProgressCallback>,
+  ) -> Result<RestoreSummary, Box<dyn std::error::Error>> {
+    self.db_manager.ensure_writable()?;
+    let mut db_manager = self.db_manager.clone();
+    let overwrite = db_manager.database_exists(db_name) && mode == RestoreMode::Overwrite;
+    if db_manager.database_exists(db_name) && mode == RestoreMode::Error {
+      return Err(format!("Database '{}' already exists locally.", db_name).into());
+    }
+    let staging_dir = db_manager.staging_dir(db_name);
+    if overwrite && Path::new(&staging_dir).exists() {
+      // Left over from a restore that didn't finish
+      fs::remove_dir_all(&staging_dir)?;
+    }
+
+    let mut downloaded = self
+      .download_database(&mut db_manager, db_name, overwrite.then_some(staging_dir.as_str()), progress)
+      .await;
+    if overwrite {
+      downloaded = downloaded.and_then(|downloaded| Self::swap_in_staged(&mut db_manager, db_name, &staging_dir).map(|_| downloaded));
+      if Path::new(&staging_dir).exists() {
+        let _ = fs::remove_dir_all(&staging_dir);
+      }
+    }
+    let (mut summary, table_samples) = downloaded?;
+    self.create_restored_tables(&mut db_manager, db_name, table_samples, &mut summary).await?;
+    Ok(summary)
+  }
+
+  // Download the objects of `db_name` into its table directories, or into `staging_dir` when one is given, returning a
+  // file of each table to infer its schema from. The database is created locally when files go to its own directory.
+  async fn download_database(
+    &self,
+    db_manager: &mut DatabaseManager,
+    db_name: &str,
+    staging_dir: Option<&str>,
+    progress: Option<&ProgressCallback>,
+  ) -> Result<(RestoreSummary, HashMap<String, String>), Box<dyn std::error::Error>> {
+    if staging_dir.is_none() && !db_manager.database_exists(db_name) {
+      db_manager.create_database(db_name)?;
+    }
+
+    // Daily files and their append segments sit under `{db}/{YYYY}/{MM}/` or `{db}/{table}/date={YYYY-MM-DD}/` (see
+    // `BucketLayout`), monthly files keep the same naming
+    let regx = Regex::new(&format!(
+      r"^{}/(?:\d{{4}}/\d{{2}}|[^/]+/date=\d{{4}}-\d{{2}}-\d{{2}})/((.+)_\d{{4}}-\d{{2}}(?:-\d{{2}}(?:_\d+)?)?\.parquet)$",
+      regex::escape(db_name)
+    ))?;
+    let objects: Vec<_> = self.s3_store.list(Some(&StorePath::from(db_name))).try_collect().await?;
+    let objects: Vec<_> = objects.into_iter().filter(|object| regx.is_match(object.location.as_ref())).collect();
+
+    let mut summary = RestoreSummary::default();
+    let mut table_samples: HashMap<String, String> = HashMap::new();
+    let mut bytes_downloaded = 0;
+    for (index, object) in objects.iter().enumerate() {
+      let key = object.location.to_string();
+      let Some(caps) = regx.captures(&key) else {
+        continue;
+      };
+      let (file_name, table_name) = (caps[1].to_string(), caps[2].to_string());
+      let table_dir = db_manager.table_dir(db_name, &table_name);
+      let local_path = format!("{}/{}", table_dir, file_name);
+      let (target_dir, target_path) = match staging_dir {
+        Some(staging_dir) => (
+          format!("{}/{}", staging_dir, table_name),
+          format!("{}/{}/{}", staging_dir, table_name, file_name),
+        ),
+        None => (table_dir, local_path.clone()),
+      };
+      table_samples.entry(table_name).or_insert_with(|| local_path.clone());
+
+      if Path::new(&target_path).exists() {
+        summary.skipped.push(local_path.clone());
+      } else {
+        fs::create_dir_all(&target_dir)?;
+        let bytes = self.get_object(&key).await?;
+        bytes_downloaded += bytes.len() as u64;
+        fs::write(&target_path, bytes)?;
+        summary.downloaded.push(local_path.clone());
+      }
+      report_progress(
+        progress,
+        SyncProgress {
+          operation: "restore",
+          file: local_path,
+          files_done: index + 1,
+          files_total: objects.len(),
+          bytes_transferred: bytes_downloaded,
+        },
+      );
+    }
+    Ok((summary, table_samples))
+  }
+
+  // Replace the local database with the one downloaded into `staging_dir`: the old database is deleted and recreated
+  // empty, then each staged table directory is moved into it
+  fn swap_in_staged(db_manager: &mut DatabaseManager, db_name: &str, staging_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    db_manager.delete_database(db_name)?;
+    db_manager.create_database(db_name)?;
+    if !Path::new(staging_dir).exists() {
+      return Ok(());
+    }
+    for entry in fs::read_dir(staging_dir)? {
+      let entry = entry?;
+      let table_name = entry.file_name().to_string_lossy().to_string();
+      fs::rename(entry.path(), db_manager.table_dir(db_name, &table_name))?;
+    }
+    Ok(())
+  }
+
+  // Create the restored tables missing from the metadata, from their uploaded schema snapshot or one of their files
+  async fn create_restored_tables(
+    &self,
+    db_manager: &mut DatabaseManager,
+    db_name: &str,
+    table_samples: HashMap<String, String>,
+    summary: &mut RestoreSummary,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    for (table_name, sample_path) in table_samples {
+      if db_manager.table_exists(db_name, &table_name) {
+        continue;
+      }
+      let schema = match self.get_object(&format!("{}/_schemas/{}.json", db_name, table_name)).await {
+        Ok(snapshot) => String::from_utf8(snapshot)?,
+        Err(_) => arrow_schema_to_table_schema(&read_parquet_arrow_schema(&sample_path)?)?.to_string(),
+      };
+      db_manager.create_table(db_name, &table_name, &schema)?;
+      summary.tables.push(table_name);
+    }
+    Ok(())
+  }
+
+  // Upload the files of a table within a date range, e.g. to re-sync the days corrected after an earlier sink. Daily
+  // files (and their append segments) go to their `BucketLayout` key, monthly files to the bucket root where
+  // `query_bucket` reads them; the Hive layout has no place for monthly files, whose partition would be a month. Files are uploaded even when an object of the same size exists, then removed locally like any sink.
+  pub async fn sink_range(
+    &self,
+    db_name: &str,
+    table_name: &str,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    granularity: SinkGranularity,
+    progress: Option<&ProgressCallback>,
+  ) -> Result<SinkSummary, Box<dyn std::error::Error>> {
+    // Synced files are removed locally
+    self.db_manager.ensure_writable()?;
+    let table_path = self
+      .db_manager
+      .get_table_path(db_name, table_name)?
+      .ok_or_else(|| format!("Database '{}' or Table '{}' does not exist.", db_name, table_name))?;
+
+    let files: Vec<(String, String)> = match granularity {
+      SinkGranularity::Daily => list_partition_files(&table_path, table_name)?
+        .into_iter()
+        .filter(|(date, _)| is_within_bounds(*date, start_date, end_date))
+        .filter_map(|(date, source_path)| {
+          let file_name = Path::new(&source_path).file_name()?.to_string_lossy().to_string();
+          Some((source_path, self.client_config.layout.daily_key(db_name, table_name, date, &file_name)))
+        })
+        .collect(),
+      SinkGranularity::Monthly if self.client_config.layout == BucketLayout::Hive => {
+        return Err("Monthly files can't be sunk with the 'hive' bucket layout, which partitions by day".into())
+      }
+      SinkGranularity::Monthly => list_monthly_files(&table_path, table_name)?
+        .into_iter()
+        .filter(|(month_start, _)| month_overlaps(*month_start, start_date, end_date))
+        .filter_map(|(_, source_path)| {
+          let file_name = Path::new(&source_path).file_name()?.to_string_lossy().to_string();
+          Some((source_path, file_name))
+        })
+        .collect(),
+    };
+
+    Ok(self.sink_files(files, true, progress).await)
+  }
+
+  // Sink `(local path, bucket path)` pairs one at a time, reporting progress after each
+  async fn sink_files(&self, files: Vec<(String, String)>, force: bool, progress: Option<&ProgressCallback>) -> SinkSummary {
+    let mut summary = SinkSummary::default();
+    let files_total = files.len();
+    for (index, (source_path, target_path)) in files.into_iter().enumerate() {
+      self.sink_file(source_path.clone(), target_path, force, &mut summary).await;
+      report_progress(
+        progress,
+        SyncProgress {
+          operation: "sink",
+          file: source_path,
+          files_done: index + 1,
+          files_total,
+          bytes_transferred: summary.bytes_uploaded,
+        },
+      );
+    }
+    summary
+  }
+
+  // Upload one local file and remove it once it's in the bucket, recording the outcome. Unless `force`, a file whose
+  // object already exists with the same size was synced before and only needs the local cleanup.
+  async fn sink_file(&self, source_path: String, target_path: String, force: bool, summary: &mut SinkSummary) {
+    let file_size = fs::metadata(&source_path).map(|m| m.len()).unwrap_or(0);
+    let already_synced =
+      !force && matches!(self.s3_store.head(&StorePath::from(target_path.as_str())).await, Ok(object) if object.size as u64 == file_size);
+    if already_synced {
+      summary.skipped.push(target_path);
+    } else if let Err(e) = self.upload_to_bucket(&source_path, &target_path).await {
+      // Keep the local file so the next sink retries it
+      summary.failed.push(SinkFailure {
+        file: source_path,
+        error: e.to_string(),
+      });
+      return;
+    } else {
+      summary.bytes_uploaded += file_size;
+      summary.uploaded.push(target_path);
+    }
+    // Clean up the local file once it is safely in the bucket
+    if let Err(e) = fs::remove_file(&source_path) {
+      summary.failed.push(SinkFailure {
+        file: source_path,
+        error: format!("Uploaded but failed to remove the local file: {}", e),
+      });
+    }
+  }
+
   async fn upload_to_bucket(&self, source_path: &str, target_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let s3_store = &self.s3_store;
     let object_store = Arc::new(s3_store);
@@ -126,14 +853,22 @@ impl CloudStorageManager {
     let mut file = tokio::fs::File::open(source_path).await?;
     let mut data = Vec::new();
     file.read_to_end(&mut data).await?;
+    let size = data.len();
     object_store.put(&StorePath::from(target_path), data.into()).await?;
+    stats::record_bucket_upload(size);
 
     Ok(())
   }
 
   #[allow(dead_code)]
-  pub async fn sink_daily_parquet(&self, db_name: &str, table_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let dir_path = &self.db_manager.get_table_path(db_name, table_name);
+  pub async fn sink_daily_parquet(
+    &self,
+    db_name: &str,
+    table_name: &str,
+    progress: Option<&ProgressCallback>,
+  ) -> Result<SinkSummary, Box<dyn std::error::Error>> {
+    self.db_manager.ensure_writable()?;
+    let dir_path = &self.db_manager.get_table_path(db_name, table_name)?;
     if dir_path.is_none() {
       return Err(format!("Database '{}' or Table '{}' does not exist.", db_name, table_name).into());
     }
@@ -145,26 +880,25 @@ impl CloudStorageManager {
       .map(|entry| entry.path().to_string_lossy().to_string())
       .collect::<Vec<_>>();
 
-    let regx = Regex::new(r"(\d{4})-(\d{2})-(\d{2})\.parquet$")?; // capture YYYY-MM-DD part of the filename
+    let regx = Regex::new(r"(\d{4}-\d{2}-\d{2})(?:_\d+)?\.parquet$")?; // capture YYYY-MM-DD (and append segment) part of the filename
 
+    let mut sink_files = Vec::new();
     for file in files {
       if let Some(filename) = Path::new(&file).file_name().and_then(|n| n.to_str()) {
         if let Some(caps) = regx.captures(filename) {
-          let year = caps.get(1).map_or("", |m| m.as_str());
-          let month = caps.get(2).map_or("", |m| m.as_str());
-          let day_extension = caps.get(0).map_or("", |m| m.as_str()); // Full day_extension string YYYY-MM-DD.parquet
-
-          let source_path = format!("{}/{}_{}", dir_path.clone().unwrap(), table_name, day_extension);
-          let target_path = format!("{}/{}/{}/{}_{}", db_name, year, month, table_name, day_extension);
-          if let Err(e) = self.upload_to_bucket(&source_path, &target_path).await {
-            eprintln!("Failed to upload file {} to S3 path {}: {:?}", source_path, target_path, e);
-          }
-          // Optional: Clean up the local file after upload
-          fs::remove_file(&source_path)?;
+          let Ok(date) = NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d") else {
+            continue;
+          };
+          let day_extension = caps.get(0).map_or("", |m| m.as_str()); // Full day_extension string YYYY-MM-DD[_n].parquet
+
+          let file_name = format!("{}_{}", table_name, day_extension);
+          let source_path = format!("{}/{}", dir_path.clone().unwrap(), file_name);
+          let target_path = self.client_config.layout.daily_key(db_name, table_name, date, &file_name);
+          sink_files.push((source_path, target_path));
         }
       }
     }
 
-    Ok(())
+    Ok(self.sink_files(sink_files, false, progress).await)
   }
 }
@@ -0,0 +1,209 @@
+use super::helpers::parse_non_finite_float;
+use arrow::array::{Array, ArrayRef, AsArray, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMillisecondArray};
+use arrow::datatypes::{DataType, Field as ArrowField, Schema};
+use base64::{engine::general_purpose, Engine as _};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use serde_json::Value;
+use std::sync::Arc;
+
+// Types a field can be declared `encrypted` with; the other ones have no single value to encrypt
+pub const ENCRYPTABLE_TYPES: [&str; 7] = ["string", "int", "uint", "float", "decimal", "bool", "timestamp"];
+
+// Encrypts the values of the fields a table schema declares `encrypted: true` with AES-256-GCM, under subkeys derived
+// from the 32-byte key the store was opened with.
+//
+// The nonce of a value is derived from the field and the value, so equal values of a field encrypt to the same text:
+// unique fields, upserts and equality predicates keep working on the stored values, at the cost of revealing which rows
+// share a value. The field name is authenticated with every value, so a value moved to another field doesn't decrypt.
+pub struct FieldCipher {
+  key: LessSafeKey,
+  nonce_key: hmac::Key,
+}
+
+impl FieldCipher {
+  // Key given base64 encoded, as it's passed at init
+  pub fn from_base64(encoded_key: &str) -> Result<Self, String> {
+    let master_key = general_purpose::STANDARD
+      .decode(encoded_key.trim())
+      .map_err(|e| format!("Invalid encryption key, expected 32 base64 encoded bytes: {}", e))?;
+    if master_key.len() != 32 {
+      return Err(format!("Invalid encryption key, expected 32 bytes but got {}", master_key.len()));
+    }
+    let master_key = hmac::Key::new(hmac::HMAC_SHA256, &master_key);
+    let encryption_key = hmac::sign(&master_key, b"timon field encryption");
+    let nonce_key = hmac::sign(&master_key, b"timon field nonce");
+    let key = UnboundKey::new(&AES_256_GCM, encryption_key.as_ref()).map_err(|_| "Invalid encryption key".to_owned())?;
+    Ok(FieldCipher {
+      key: LessSafeKey::new(key),
+      nonce_key: hmac::Key::new(hmac::HMAC_SHA256, nonce_key.as_ref()),
+    })
+  }
+
+  // Encrypt a JSON value of `field` into the base64 text of its nonce, ciphertext and tag
+  pub fn encrypt(&self, field: &str, value: &Value) -> Result<String, String> {
+    let mut in_out = value.to_string().into_bytes();
+    let mut nonce_input = Vec::with_capacity(field.len() + 1 + in_out.len());
+    nonce_input.extend_from_slice(field.as_bytes());
+    nonce_input.push(0);
+    nonce_input.extend_from_slice(&in_out);
+    let nonce_bytes = hmac::sign(&self.nonce_key, &nonce_input);
+    let nonce_bytes = &nonce_bytes.as_ref()[..NONCE_LEN];
+    let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).map_err(|_| "Invalid nonce".to_owned())?;
+    self
+      .key
+      .seal_in_place_append_tag(nonce, Aad::from(field.as_bytes()), &mut in_out)
+      .map_err(|_| format!("Failed to encrypt field '{}'", field))?;
+    let mut sealed = nonce_bytes.to_vec();
+    sealed.extend_from_slice(&in_out);
+    Ok(general_purpose::STANDARD.encode(sealed))
+  }
+
+  // Decrypt a value `encrypt` produced for `field` back into its JSON value
+  pub fn decrypt(&self, field: &str, text: &str) -> Result<Value, String> {
+    let decrypt_error = || format!("Failed to decrypt field '{}': wrong key or corrupted value", field);
+    let sealed = general_purpose::STANDARD.decode(text).map_err(|_| decrypt_error())?;
+    if sealed.len() < NONCE_LEN {
+      return Err(decrypt_error());
+    }
+    let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).map_err(|_| decrypt_error())?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = self
+      .key
+      .open_in_place(nonce, Aad::from(field.as_bytes()), &mut in_out)
+      .map_err(|_| decrypt_error())?;
+    serde_json::from_slice(plaintext).map_err(|_| decrypt_error())
+  }
+}
+
+// Fields of a table schema declared `encrypted: true`, with their declared type
+pub fn encrypted_fields(table_schema: &Value) -> Vec<(String, String)> {
+  let Some(fields) = table_schema.as_object() else {
+    return Vec::new();
+  };
+  fields
+    .iter()
+    .filter(|(_, rules)| rules.get("encrypted").and_then(Value::as_bool) == Some(true))
+    .map(|(field, rules)| (field.clone(), rules.get("type").and_then(Value::as_str).unwrap_or("string").to_owned()))
+    .collect()
+}
+
+// The table schema the files are written with, where the encrypted fields hold the text of their values
+pub fn storage_schema(table_schema: &Value) -> Value {
+  let mut storage_schema = table_schema.clone();
+  for (field, _) in encrypted_fields(table_schema) {
+    if let Some(rules) = storage_schema.get_mut(&field).and_then(Value::as_object_mut) {
+      rules.insert("type".to_owned(), Value::String("string".to_owned()));
+    }
+  }
+  storage_schema
+}
+
+// Replace the values of the encrypted fields of validated rows by their encrypted text, failing when the table has
+// encrypted fields but the store was opened without a key
+pub fn encrypt_rows(cipher: Option<&FieldCipher>, table_schema: &Value, rows: &mut [Value]) -> Result<(), String> {
+  let fields = encrypted_fields(table_schema);
+  if fields.is_empty() {
+    return Ok(());
+  }
+  let Some(cipher) = cipher else {
+    let names: Vec<&str> = fields.iter().map(|(field, _)| field.as_str()).collect();
+    return Err(format!(
+      "Fields '{}' are encrypted, the store must be opened with an encryption key to insert into this table",
+      names.join("', '")
+    ));
+  };
+  for record in rows.iter_mut().filter_map(Value::as_object_mut) {
+    for (field, _) in &fields {
+      if let Some(value) = record.get_mut(field).filter(|value| !value.is_null()) {
+        *value = Value::String(cipher.encrypt(field, value)?);
+      }
+    }
+  }
+  Ok(())
+}
+
+// Decrypt the encrypted fields of rows read back from a write-ahead log, which holds them as they're written
+pub fn decrypt_rows(cipher: Option<&FieldCipher>, table_schema: &Value, rows: &mut [Value]) -> Result<(), String> {
+  let fields = encrypted_fields(table_schema);
+  if fields.is_empty() {
+    return Ok(());
+  }
+  let cipher = cipher.ok_or("The store must be opened with its encryption key to replay inserts into encrypted fields")?;
+  for record in rows.iter_mut().filter_map(Value::as_object_mut) {
+    for (field, _) in &fields {
+      if let Some(Value::String(text)) = record.get(field).filter(|value| value != &&Value::String(String::new())) {
+        let value = cipher.decrypt(field, text)?;
+        record.insert(field.clone(), value);
+      }
+    }
+  }
+  Ok(())
+}
+
+// Decrypt the encrypted fields of queried batches back into columns of their declared type, empty text (a missing value
+// of a rewritten file) reading as null. Without a key (or for a column that isn't text, e.g. read with a schema
+// override) the stored text is left as is.
+pub fn decrypt_batches(cipher: Option<&FieldCipher>, table_schema: &Value, batches: Vec<RecordBatch>) -> DataFusionResult<Vec<RecordBatch>> {
+  let fields = encrypted_fields(table_schema);
+  let Some(cipher) = cipher.filter(|_| !fields.is_empty()) else {
+    return Ok(batches);
+  };
+  batches.into_iter().map(|batch| decrypt_batch(cipher, &fields, batch)).collect()
+}
+
+fn decrypt_batch(cipher: &FieldCipher, fields: &[(String, String)], batch: RecordBatch) -> DataFusionResult<RecordBatch> {
+  let schema = batch.schema();
+  let mut columns = batch.columns().to_vec();
+  let mut arrow_fields: Vec<ArrowField> = schema.fields().iter().map(|field| field.as_ref().clone()).collect();
+  for (field, field_type) in fields {
+    let Ok(index) = schema.index_of(field) else {
+      continue;
+    };
+    if columns[index].data_type() != &DataType::Utf8 {
+      continue;
+    }
+    let values = columns[index]
+      .as_string::<i32>()
+      .iter()
+      .map(|text| text.filter(|text| !text.is_empty()).map(|text| cipher.decrypt(field, text)).transpose())
+      .collect::<Result<Vec<Option<Value>>, String>>()
+      .map_err(DataFusionError::Execution)?;
+    let column = decrypted_array(field_type, values);
+    arrow_fields[index] = ArrowField::new(field, column.data_type().clone(), true);
+    columns[index] = column;
+  }
+  let schema = Schema::new_with_metadata(arrow_fields, schema.metadata().clone());
+  Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+// Build the column of a declared type from the decrypted values, as the insert path would have stored them
+fn decrypted_array(field_type: &str, values: Vec<Option<Value>>) -> ArrayRef {
+  let values = values.into_iter().map(|value| value.filter(|value| !value.is_null()));
+  match field_type {
+    "int" | "uint" => Arc::new(values.map(|value| value.and_then(|value| value.as_i64())).collect::<Int64Array>()),
+    "timestamp" => Arc::new(
+      values
+        .map(|value| value.and_then(|value| value.as_i64()))
+        .collect::<TimestampMillisecondArray>(),
+    ),
+    "float" | "decimal" => Arc::new(values.map(|value| value.and_then(|value| as_float(&value))).collect::<Float64Array>()),
+    "bool" => Arc::new(values.map(|value| value.and_then(|value| value.as_bool())).collect::<BooleanArray>()),
+    _ => Arc::new(
+      values
+        .map(|value| value.map(|value| value.as_str().map_or_else(|| value.to_string(), str::to_owned)))
+        .collect::<StringArray>(),
+    ),
+  }
+}
+
+// Float fields also take the `NaN`/`Infinity` strings JSON has no number for
+fn as_float(value: &Value) -> Option<f64> {
+  match value {
+    Value::String(text) => parse_non_finite_float(text),
+    _ => value.as_f64(),
+  }
+}
@@ -1,28 +1,297 @@
 use arrow::array::{
-  Array, ArrayRef, BooleanArray, BooleanBuilder, Float64Array, Float64Builder, Int64Array, Int64Builder, ListArray, ListBuilder, StringArray,
-  StringBuilder, TimestampMillisecondArray,
+  Array, ArrayRef, AsArray, BinaryArray, BooleanArray, BooleanBuilder, Float64Array, Float64Builder, Int64Array, Int64Builder, ListArray,
+  ListBuilder, StringArray, StringBuilder,
 };
-use arrow::datatypes::{DataType, Field as ArrowField, Schema, TimeUnit};
+use arrow::compute::cast;
+use arrow::datatypes::{
+  ArrowNativeType, DataType, Date32Type, Decimal128Type, Decimal256Type, DecimalType, Field as ArrowField, Float32Type, Int16Type, Int32Type,
+  Int64Type, Int8Type, Schema, TimeUnit, TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType,
+  UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+use arrow::json::ReaderBuilder;
 use base64::{engine::general_purpose, Engine as _};
-use chrono::{Datelike, NaiveDate, ParseError};
+use chrono::{DateTime, Datelike, Days, FixedOffset, Months, NaiveDate, NaiveDateTime, NaiveTime, ParseError, Utc};
 use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use datafusion::prelude::ParquetReadOptions;
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::{visit_relations, SetExpr, Statement as SQLStatement};
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::TableReference;
+use futures::StreamExt;
+use parquet::arrow::parquet_to_arrow_schema;
+use parquet::basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel};
 use parquet::data_type::{AsBytes, Decimal};
+use parquet::file::metadata::KeyValue;
+use parquet::file::reader::{FileReader, SerializedFileReader};
 use parquet::record::{Field as ParquetField, Row};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::path::Path;
 use std::sync::Arc;
 
-pub fn record_batches_to_json(batches: &[RecordBatch]) -> Result<Value, serde_json::Error> {
+// How NaN/Infinity floats, which JSON can't represent, are emitted in query results
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NonFiniteFloats {
+  #[default]
+  Null, // Emit `null`
+  String, // Emit "NaN", "Infinity" or "-Infinity", the spellings accepted back by `insert`
+}
+
+pub fn float_to_json(value: f64, non_finite_floats: NonFiniteFloats) -> Value {
+  if value.is_finite() {
+    return json!(value);
+  }
+  match non_finite_floats {
+    NonFiniteFloats::Null => Value::Null,
+    NonFiniteFloats::String => json!(non_finite_to_string(value)),
+  }
+}
+
+fn non_finite_to_string(value: f64) -> &'static str {
+  if value.is_nan() {
+    "NaN"
+  } else if value > 0.0 {
+    "Infinity"
+  } else {
+    "-Infinity"
+  }
+}
+
+// Parse the string spellings of non-finite floats, since JSON numbers can't hold them
+pub fn parse_non_finite_float(value: &str) -> Option<f64> {
+  match value {
+    "NaN" => Some(f64::NAN),
+    "Infinity" => Some(f64::INFINITY),
+    "-Infinity" => Some(f64::NEG_INFINITY),
+    _ => None,
+  }
+}
+
+// Representation of a query result handed back to the caller
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+  #[default]
+  Json, // Array of row objects
+  ArrowIpc, // Base64 of an Arrow IPC stream, for consumers that ingest Arrow directly
+  Csv,      // CSV text with a header row
+}
+
+impl OutputFormat {
+  // An empty name keeps the default JSON output
+  pub fn parse(name: &str) -> Result<Self, String> {
+    match name.trim().to_lowercase().as_str() {
+      "" | "json" => Ok(OutputFormat::Json),
+      "arrow_ipc" => Ok(OutputFormat::ArrowIpc),
+      "csv" => Ok(OutputFormat::Csv),
+      other => Err(format!("Unsupported output format '{}', expected 'json', 'arrow_ipc' or 'csv'", other)),
+    }
+  }
+}
+
+// How an insert picks the Arrow types of the columns it writes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaSource {
+  #[default]
+  InferAll, // Scan every row, promoting a column holding both integers and floats to floats
+  Declared, // The table schema's types, writing every declared column even when no row of the batch has it
+  FirstRow, // The first row's types, which the other rows must fit
+}
+
+// Compression of a payload handed to `insert_compressed`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionCodec {
+  Gzip,
+  Zstd,
+}
+
+impl CompressionCodec {
+  pub fn parse(name: &str) -> Result<Self, String> {
+    match name.trim().to_lowercase().as_str() {
+      "gzip" | "gz" => Ok(CompressionCodec::Gzip),
+      "zstd" | "zst" => Ok(CompressionCodec::Zstd),
+      other => Err(format!("Unsupported compression codec '{}', expected 'gzip' or 'zstd'", other)),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      CompressionCodec::Gzip => "gzip",
+      CompressionCodec::Zstd => "zstd",
+    }
+  }
+
+  // A reader decompressing `data` as it's read; concatenated gzip members are read through like `gunzip` does
+  pub fn decoder<'a, R: Read + 'a>(&self, data: R) -> std::io::Result<Box<dyn Read + 'a>> {
+    match self {
+      CompressionCodec::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(data))),
+      CompressionCodec::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(data)?)),
+    }
+  }
+}
+
+// Compression of the Parquet files the inserts write, from a codec name (`uncompressed`, `snappy`, `gzip`, `zstd`,
+// `lz4` or `brotli`), optionally with a level such as `zstd(9)`; a codec taking a level gets its default one without
+pub fn parse_parquet_compression(codec: &str) -> Result<Compression, String> {
+  let codec = codec.trim().to_lowercase();
+  let compression = match codec.as_str() {
+    "uncompressed" | "none" => Compression::UNCOMPRESSED,
+    "snappy" => Compression::SNAPPY,
+    "gzip" => Compression::GZIP(GzipLevel::default()),
+    "zstd" => Compression::ZSTD(ZstdLevel::default()),
+    "lz4" => Compression::LZ4_RAW,
+    "brotli" => Compression::BROTLI(BrotliLevel::default()),
+    _ if ["gzip(", "zstd(", "brotli("].iter().any(|prefix| codec.starts_with(prefix)) => {
+      codec.parse().map_err(|e| format!("Invalid compression '{}': {}", codec, e))?
+    }
+    _ => {
+      return Err(format!(
+        "Unsupported compression '{}', expected 'uncompressed', 'snappy', 'gzip', 'zstd', 'lz4' or 'brotli'",
+        codec
+      ))
+    }
+  };
+  Ok(compression)
+}
+
+pub fn record_batches_to_output(batches: &[RecordBatch], format: OutputFormat, non_finite_floats: NonFiniteFloats) -> Result<Value, Box<dyn Error>> {
+  match format {
+    OutputFormat::Json => Ok(record_batches_to_json(batches, non_finite_floats)?),
+    OutputFormat::ArrowIpc => {
+      let mut buffer = Vec::new();
+      if let Some(first_batch) = batches.first() {
+        let mut writer = arrow::ipc::writer::StreamWriter::try_new(&mut buffer, &first_batch.schema())?;
+        for batch in batches {
+          writer.write(batch)?;
+        }
+        writer.finish()?;
+      }
+      Ok(Value::String(general_purpose::STANDARD.encode(buffer)))
+    }
+    OutputFormat::Csv => {
+      let mut buffer = Vec::new();
+      {
+        let mut writer = arrow::csv::WriterBuilder::new().with_header(true).build(&mut buffer);
+        for batch in batches {
+          writer.write(batch)?;
+        }
+      }
+      Ok(Value::String(String::from_utf8(buffer)?))
+    }
+  }
+}
+
+// Keep only `columns` of a query result, in the listed order, so a `SELECT *` from a generic query builder serializes
+// just the columns the caller shows. A column missing from the result is an error naming the available ones.
+pub fn project_record_batches(batches: Vec<RecordBatch>, columns: &[String]) -> DataFusionResult<Vec<RecordBatch>> {
+  let Some(schema) = batches.first().map(|batch| batch.schema()) else {
+    return Ok(batches);
+  };
+  let indices = columns
+    .iter()
+    .map(|column| {
+      schema.index_of(column).map_err(|_| {
+        let available: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+        DataFusionError::Plan(format!(
+          "Column '{}' is not in the query result (columns: {})",
+          column,
+          available.join(", ")
+        ))
+      })
+    })
+    .collect::<DataFusionResult<Vec<usize>>>()?;
+  batches.iter().map(|batch| Ok(batch.project(&indices)?)).collect()
+}
+
+pub fn record_batches_to_json(batches: &[RecordBatch], non_finite_floats: NonFiniteFloats) -> Result<Value, serde_json::Error> {
   // println!("batches >>> {:?}", batches);
-  fn array_value_to_json(array: &ArrayRef, row_index: usize) -> serde_json::Value {
+  // A decimal formatted from its unscaled integer and scale: a JSON integer when it has no fractional digits and fits
+  // an i64, otherwise its exact digits as a string (like the decimals of `row_to_json`), which an f64 could round
+  fn decimal_to_json(digits: String, scale: i8) -> Value {
+    match digits.parse::<i64>() {
+      Ok(integer) if scale <= 0 => json!(integer),
+      _ => json!(digits),
+    }
+  }
+
+  // Read a single key of a dictionary's keys array, which holds one of the integer types
+  fn dictionary_key(keys: &dyn Array, row_index: usize) -> usize {
+    match keys.data_type() {
+      DataType::Int8 => keys.as_primitive::<Int8Type>().value(row_index).as_usize(),
+      DataType::Int16 => keys.as_primitive::<Int16Type>().value(row_index).as_usize(),
+      DataType::Int32 => keys.as_primitive::<Int32Type>().value(row_index).as_usize(),
+      DataType::Int64 => keys.as_primitive::<Int64Type>().value(row_index).as_usize(),
+      DataType::UInt8 => keys.as_primitive::<UInt8Type>().value(row_index).as_usize(),
+      DataType::UInt16 => keys.as_primitive::<UInt16Type>().value(row_index).as_usize(),
+      DataType::UInt32 => keys.as_primitive::<UInt32Type>().value(row_index).as_usize(),
+      DataType::UInt64 => keys.as_primitive::<UInt64Type>().value(row_index).as_usize(),
+      key_type => unreachable!("dictionary keys of type {}", key_type),
+    }
+  }
+  fn array_value_to_json(array: &ArrayRef, row_index: usize, non_finite_floats: NonFiniteFloats) -> serde_json::Value {
+    // Null slots hold an arbitrary default value, so they must be checked before reading it
+    if array.is_null(row_index) {
+      return json!(null);
+    }
     match array.data_type() {
       DataType::Int64 => json!(array.as_any().downcast_ref::<Int64Array>().unwrap().value(row_index)),
-      DataType::Float64 => json!(array.as_any().downcast_ref::<Float64Array>().unwrap().value(row_index)),
+      DataType::Float64 => float_to_json(array.as_any().downcast_ref::<Float64Array>().unwrap().value(row_index), non_finite_floats),
       DataType::Utf8 => json!(array.as_any().downcast_ref::<StringArray>().unwrap().value(row_index)),
       DataType::Boolean => json!(array.as_any().downcast_ref::<BooleanArray>().unwrap().value(row_index)),
-      DataType::Timestamp(TimeUnit::Millisecond, None) => json!(array.as_any().downcast_ref::<TimestampMillisecondArray>().unwrap().value(row_index)),
+      DataType::Int32 => json!(array.as_primitive::<Int32Type>().value(row_index)),
+      DataType::Int16 => json!(array.as_primitive::<Int16Type>().value(row_index)),
+      DataType::Int8 => json!(array.as_primitive::<Int8Type>().value(row_index)),
+      DataType::Float32 => float_to_json(array.as_primitive::<Float32Type>().value(row_index) as f64, non_finite_floats),
+      // Aggregates such as COUNT/SUM over unsigned or decimal columns produce these result types
+      DataType::UInt64 => json!(array.as_primitive::<UInt64Type>().value(row_index)),
+      DataType::UInt32 => json!(array.as_primitive::<UInt32Type>().value(row_index)),
+      DataType::UInt16 => json!(array.as_primitive::<UInt16Type>().value(row_index)),
+      DataType::UInt8 => json!(array.as_primitive::<UInt8Type>().value(row_index)),
+      DataType::Decimal128(precision, scale) => decimal_to_json(
+        Decimal128Type::format_decimal(array.as_primitive::<Decimal128Type>().value(row_index), *precision, *scale),
+        *scale,
+      ),
+      DataType::Decimal256(precision, scale) => decimal_to_json(
+        Decimal256Type::format_decimal(array.as_primitive::<Decimal256Type>().value(row_index), *precision, *scale),
+        *scale,
+      ),
+      DataType::LargeUtf8 => json!(array.as_string::<i64>().value(row_index)),
+      // Bytes are emitted base64-encoded, which `binary` fields accept back on insert
+      DataType::Binary => json!(general_purpose::STANDARD.encode(array.as_binary::<i32>().value(row_index))),
+      DataType::LargeBinary => json!(general_purpose::STANDARD.encode(array.as_binary::<i64>().value(row_index))),
+      DataType::Date32 => json!(array.as_primitive::<Date32Type>().value_as_date(row_index).map(|date| date.to_string())),
+      // Timestamps of every unit (e.g. Int96 columns read as nanoseconds) are emitted as epoch milliseconds
+      DataType::Timestamp(TimeUnit::Millisecond, _) => json!(array.as_primitive::<TimestampMillisecondType>().value(row_index)),
+      DataType::Timestamp(TimeUnit::Second, _) => json!(array.as_primitive::<TimestampSecondType>().value(row_index) * 1_000),
+      DataType::Timestamp(TimeUnit::Microsecond, _) => json!(array.as_primitive::<TimestampMicrosecondType>().value(row_index) / 1_000),
+      DataType::Timestamp(TimeUnit::Nanosecond, _) => json!(array.as_primitive::<TimestampNanosecondType>().value(row_index) / 1_000_000),
+      // Dictionary-encoded columns resolve the row's key into the dictionary values
+      DataType::Dictionary(_, _) => {
+        let dictionary = array.as_any_dictionary();
+        array_value_to_json(dictionary.values(), dictionary_key(dictionary.keys(), row_index), non_finite_floats)
+      }
+      // Flat groups become nested JSON objects
+      DataType::Struct(fields) => {
+        let struct_array = array.as_struct();
+        let object: serde_json::Map<_, _> = fields
+          .iter()
+          .zip(struct_array.columns())
+          .map(|(field, column)| (field.name().clone(), array_value_to_json(column, row_index, non_finite_floats)))
+          .collect();
+        Value::Object(object)
+      }
       DataType::List(_inner_field) => {
         let list_array = array.as_any().downcast_ref::<ListArray>().unwrap();
         let offsets = list_array.value_offsets();
@@ -31,29 +300,55 @@ pub fn record_batches_to_json(batches: &[RecordBatch]) -> Result<Value, serde_js
         let values_array = list_array.values();
 
         // Recursive function to handle nested lists
-        fn extract_list_values(array: &dyn Array, start_idx: usize, end_idx: usize) -> Vec<serde_json::Value> {
+        fn extract_list_values(array: &dyn Array, start_idx: usize, end_idx: usize, non_finite_floats: NonFiniteFloats) -> Vec<serde_json::Value> {
           match array.data_type() {
             DataType::Utf8 => {
               let string_array = array.as_any().downcast_ref::<StringArray>().unwrap();
-              (start_idx..end_idx).map(|i| json!(string_array.value(i))).collect()
+              (start_idx..end_idx)
+                .map(|i| {
+                  if string_array.is_null(i) {
+                    json!(null)
+                  } else {
+                    json!(string_array.value(i))
+                  }
+                })
+                .collect()
             }
             DataType::Int64 => {
               let int_array = array.as_any().downcast_ref::<Int64Array>().unwrap();
-              (start_idx..end_idx).map(|i| json!(int_array.value(i))).collect()
+              (start_idx..end_idx)
+                .map(|i| if int_array.is_null(i) { json!(null) } else { json!(int_array.value(i)) })
+                .collect()
             }
             DataType::Float64 => {
               let float_array = array.as_any().downcast_ref::<Float64Array>().unwrap();
-              (start_idx..end_idx).map(|i| json!(float_array.value(i))).collect()
+              (start_idx..end_idx)
+                .map(|i| {
+                  if float_array.is_null(i) {
+                    json!(null)
+                  } else {
+                    float_to_json(float_array.value(i), non_finite_floats)
+                  }
+                })
+                .collect()
             }
             DataType::Boolean => {
               let bool_array = array.as_any().downcast_ref::<BooleanArray>().unwrap();
-              (start_idx..end_idx).map(|i| json!(bool_array.value(i))).collect()
+              (start_idx..end_idx)
+                .map(|i| {
+                  if bool_array.is_null(i) {
+                    json!(null)
+                  } else {
+                    json!(bool_array.value(i))
+                  }
+                })
+                .collect()
             }
             _ => Vec::new(),
           }
         }
 
-        let values = extract_list_values(values_array.as_ref(), start_idx, end_idx);
+        let values = extract_list_values(values_array.as_ref(), start_idx, end_idx, non_finite_floats);
         json!(values)
       }
       _ => json!(null),
@@ -69,7 +364,7 @@ pub fn record_batches_to_json(batches: &[RecordBatch]) -> Result<Value, serde_js
       (0..num_rows).map(move |row_index| {
         schema.fields().iter().enumerate().fold(HashMap::new(), |mut row, (col_index, field)| {
           let column = batch.column(col_index);
-          row.insert(field.name().clone(), array_value_to_json(column, row_index));
+          row.insert(field.name().clone(), array_value_to_json(column, row_index, non_finite_floats));
           row
         })
       })
@@ -79,6 +374,101 @@ pub fn record_batches_to_json(batches: &[RecordBatch]) -> Result<Value, serde_js
   serde_json::to_value(&rows)
 }
 
+// Run `sql_query` over a single Parquet file registered as the table `timon`, whatever wrote it
+pub async fn query_parquet_file(ctx: &SessionContext, file_path: &str, sql_query: &str) -> DataFusionResult<DataFrame> {
+  ctx.register_parquet("timon", file_path, ParquetReadOptions::default()).await?;
+  ctx.sql(sql_query).await
+}
+
+// Collect a DataFrame batch by batch and fail as soon as the result exceeds `max_rows`/`max_bytes`,
+// so oversized results surface as an error instead of exhausting memory
+pub async fn collect_with_limits(df: DataFrame, max_rows: Option<usize>, max_bytes: Option<usize>) -> DataFusionResult<Vec<RecordBatch>> {
+  if max_rows.is_none() && max_bytes.is_none() {
+    return df.collect().await;
+  }
+
+  let mut stream = df.execute_stream().await?;
+  let (mut total_rows, mut total_bytes) = (0, 0);
+  let mut batches = Vec::new();
+  while let Some(batch) = stream.next().await {
+    let batch = batch?;
+    total_rows += batch.num_rows();
+    total_bytes += batch.get_array_memory_size();
+    if max_rows.is_some_and(|max| total_rows > max) || max_bytes.is_some_and(|max| total_bytes > max) {
+      return Err(DataFusionError::ResourcesExhausted(format!(
+        "Query result too large (over {} rows / {} bytes so far), add a LIMIT or narrow the date range",
+        total_rows, total_bytes
+      )));
+    }
+    batches.push(batch);
+  }
+  Ok(batches)
+}
+
+// Join registered tables with UNION ALL, selecting the same columns by name and in sorted order from each one, so files
+// holding their columns in another order line up instead of being matched by position; a column missing from a file
+// reads as null. With `file_order`, each row also gets its table's index in `table_names` as `__timon_file_order`.
+pub async fn union_all_by_name(ctx: &SessionContext, table_names: &[String], file_order: bool) -> DataFusionResult<String> {
+  union_all_with_sources(ctx, table_names, file_order, None).await
+}
+
+// Same as `union_all_by_name`, with each row also tagged with its table's `(partition, file name)` from `sources` (one
+// per table) as the `_partition` and `_source_file` columns, unless the tables already have columns of those names
+pub async fn union_all_with_sources(
+  ctx: &SessionContext,
+  table_names: &[String],
+  file_order: bool,
+  sources: Option<&[(String, String)]>,
+) -> DataFusionResult<String> {
+  let mut table_columns = Vec::with_capacity(table_names.len());
+  for table_name in table_names {
+    let schema = ctx.table_provider(table_name.as_str()).await?.schema();
+    table_columns.push(schema.fields().iter().map(|field| field.name().clone()).collect::<HashSet<String>>());
+  }
+  let columns: BTreeSet<&String> = table_columns.iter().flatten().collect();
+  if sources.is_some() {
+    if let Some(column) = ["_partition", "_source_file"]
+      .iter()
+      .find(|name| columns.iter().any(|column| column == *name))
+    {
+      return Err(DataFusionError::Plan(format!(
+        "The table already has a '{}' column, so the source columns can't be added",
+        column
+      )));
+    }
+  }
+
+  let selects: Vec<String> = table_names
+    .iter()
+    .zip(&table_columns)
+    .enumerate()
+    .map(|(order, (table_name, present_columns))| {
+      let mut projection: Vec<String> = columns
+        .iter()
+        .map(|column| {
+          let quoted = format!("\"{}\"", column.replace('"', "\"\""));
+          if present_columns.contains(*column) {
+            quoted
+          } else {
+            format!("NULL AS {}", quoted)
+          }
+        })
+        .collect();
+      if let Some((partition, file)) = sources.map(|sources| &sources[order]) {
+        projection.push(format!("'{}' AS _partition", partition.replace('\'', "''")));
+        projection.push(format!("'{}' AS _source_file", file.replace('\'', "''")));
+      }
+      if file_order {
+        projection.push(format!("{} AS __timon_file_order", order));
+      }
+      format!("SELECT {} FROM {}", projection.join(", "), table_name)
+    })
+    .collect();
+  Ok(selects.join(" UNION ALL "))
+}
+
+const UNIX_EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
 pub fn row_to_json(row: &Row) -> serde_json::Value {
   fn parquet_value_to_json(value: &ParquetField) -> serde_json::Value {
     fn decimal_to_string(decimal: &Decimal) -> String {
@@ -96,15 +486,33 @@ pub fn row_to_json(row: &Row) -> serde_json::Value {
       ParquetField::Short(s) => json!(*s),
       ParquetField::Int(i) => json!(*i),
       ParquetField::Long(l) => json!(*l),
-      ParquetField::Float(f) => json!(*f),
-      ParquetField::Double(d) => json!(*d),
+      // Non-finite floats keep their string spelling so rewriting the daily file doesn't lose them
+      ParquetField::Float(f) => float_to_json(*f as f64, NonFiniteFloats::String),
+      ParquetField::Double(d) => float_to_json(*d, NonFiniteFloats::String),
       ParquetField::Str(s) => json!(s),
       ParquetField::Bytes(b) => json!(general_purpose::STANDARD.encode(b)),
+      ParquetField::UByte(b) => json!(*b),
+      ParquetField::UShort(s) => json!(*s),
+      ParquetField::UInt(i) => json!(*i),
+      ParquetField::ULong(l) => json!(*l),
+      ParquetField::Float16(f) => json!(f.to_f32()),
+      ParquetField::Date(d) => json!(NaiveDate::from_num_days_from_ce_opt(*d + UNIX_EPOCH_DAYS_FROM_CE).map(|date| date.to_string())),
       ParquetField::TimestampMicros(t) => json!(t),
-      ParquetField::TimestampMillis(t) => json!(t),
+      ParquetField::TimestampMillis(t) => json!(t), // INT96 timestamps are converted to millis by the row reader
+      ParquetField::MapInternal(map) => {
+        let json_object: serde_json::Map<_, _> = map
+          .entries()
+          .iter()
+          .map(|(key, value)| match key {
+            ParquetField::Str(key) => (key.clone(), parquet_value_to_json(value)),
+            key => (parquet_value_to_json(key).to_string(), parquet_value_to_json(value)),
+          })
+          .collect();
+        serde_json::Value::Object(json_object)
+      }
       ParquetField::Decimal(d) => json!(decimal_to_string(d)),
       ParquetField::ListInternal(list) => {
-        let json_array: Vec<serde_json::Value> = list.elements().iter().map(|element| parquet_value_to_json(element)).collect();
+        let json_array: Vec<serde_json::Value> = list.elements().iter().map(parquet_value_to_json).collect();
         serde_json::Value::Array(json_array)
       }
       ParquetField::Group(g) => {
@@ -127,13 +535,37 @@ pub fn row_to_json(row: &Row) -> serde_json::Value {
 }
 
 pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), Box<dyn std::error::Error>> {
+  json_to_arrow_with_floats(json_values, &[])
+}
+
+// The fields a table schema declares as `float` (alone or in a type union), whose "NaN"/"Infinity"/"-Infinity"
+// strings are floats rather than text
+fn declared_float_fields(table_schema: &Value) -> Vec<&str> {
+  let Some(schema_obj) = table_schema.as_object() else {
+    return Vec::new();
+  };
+  schema_obj
+    .iter()
+    .filter(|(_, rules)| {
+      let field_type = rules.get("type").and_then(Value::as_str).unwrap_or_default();
+      field_type.split('|').any(|field_type| field_type == "float")
+    })
+    .map(|(field_name, _)| field_name.as_str())
+    .collect()
+}
+
+// Convert records like `json_to_arrow`, reading the non-finite float spellings of the `float_fields` as floats
+fn json_to_arrow_with_floats(json_values: &[Value], float_fields: &[&str]) -> Result<(Vec<ArrayRef>, Schema), Box<dyn std::error::Error>> {
   fn resolve_data_type_conflict(current: Option<DataType>, new_type: DataType) -> DataType {
     match (current, new_type) {
       (None, new) => new,
       (Some(DataType::Int64), DataType::Float64) => DataType::Float64, // Promote Int64 to Float64
       (Some(DataType::Float64), DataType::Int64) => DataType::Float64, // Promote Int64 to Float64
       (Some(current), new) if current == new => current,               // Same type
-      (_, new) => new,                                                 // Prefer the new type
+      // A null or an empty array says nothing about the field's type
+      (Some(current), DataType::Null) => current,
+      (Some(current @ DataType::List(_)), DataType::List(item)) if item.data_type() == &DataType::Null => current,
+      (_, new) => new, // Prefer the new type
     }
   }
 
@@ -141,8 +573,8 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
     return Err("No data to write".into());
   }
 
-  // Determine the schema dynamically
-  let mut field_types: std::collections::HashMap<String, DataType> = std::collections::HashMap::new();
+  // Determine the schema dynamically, with the fields sorted by name so every file of a table has the same column order
+  let mut field_types: BTreeMap<String, DataType> = BTreeMap::new();
 
   // Iterate through each JSON object to detect data types
   for obj in json_values.iter().filter_map(Value::as_object) {
@@ -151,6 +583,8 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
       let new_type = match value {
         Value::Number(num) if num.is_f64() => DataType::Float64,
         Value::Number(_) => DataType::Int64,
+        // "NaN"/"Infinity"/"-Infinity" are floats in the fields declared as such, and text anywhere else
+        Value::String(text) if float_fields.contains(&key.as_str()) && parse_non_finite_float(text).is_some() => DataType::Float64,
         Value::String(_) => DataType::Utf8,
         Value::Bool(_) => DataType::Boolean,
         Value::Array(arr) => {
@@ -190,28 +624,39 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
         DataType::Int64 => {
           let values: Vec<i64> = json_values
             .iter()
-            .map(|v| v.get(&field.name()).and_then(Value::as_i64).unwrap_or_default())
+            .map(|v| v.get(field.name()).and_then(Value::as_i64).unwrap_or_default())
             .collect();
           Arc::new(Int64Array::from(values)) as ArrayRef
         }
         DataType::Float64 => {
           let values: Vec<f64> = json_values
             .iter()
-            .map(|v| v.get(&field.name()).and_then(Value::as_f64).unwrap_or_default())
+            .map(|v| {
+              v.get(field.name())
+                .and_then(|value| {
+                  value.as_f64().or_else(|| {
+                    value
+                      .as_str()
+                      .filter(|_| float_fields.contains(&field.name().as_str()))
+                      .and_then(parse_non_finite_float)
+                  })
+                })
+                .unwrap_or_default()
+            })
             .collect();
           Arc::new(Float64Array::from(values)) as ArrayRef
         }
         DataType::Utf8 => {
           let values: Vec<String> = json_values
             .iter()
-            .map(|v| v.get(&field.name()).and_then(Value::as_str).unwrap_or_default().to_string())
+            .map(|v| v.get(field.name()).and_then(Value::as_str).unwrap_or_default().to_string())
             .collect();
           Arc::new(StringArray::from(values)) as ArrayRef
         }
         DataType::Boolean => {
           let values: Vec<bool> = json_values
             .iter()
-            .map(|v| v.get(&field.name()).and_then(Value::as_bool).unwrap_or_default())
+            .map(|v| v.get(field.name()).and_then(Value::as_bool).unwrap_or_default())
             .collect();
           Arc::new(BooleanArray::from(values)) as ArrayRef
         }
@@ -223,7 +668,7 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
               let string_builder = StringBuilder::new();
               let mut list_builder = ListBuilder::new(string_builder);
 
-              for value in json_values.iter().map(|v| v.get(&field.name())) {
+              for value in json_values.iter().map(|v| v.get(field.name())) {
                 if let Some(Value::Array(arr)) = value {
                   let string_builder = list_builder.values();
                   for item in arr {
@@ -243,7 +688,7 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
               let int_builder = Int64Builder::new();
               let mut list_builder = ListBuilder::new(int_builder);
 
-              for value in json_values.iter().map(|v| v.get(&field.name())) {
+              for value in json_values.iter().map(|v| v.get(field.name())) {
                 if let Some(Value::Array(arr)) = value {
                   let int_builder = list_builder.values();
                   for item in arr {
@@ -263,7 +708,7 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
               let float_builder = Float64Builder::new();
               let mut list_builder = ListBuilder::new(float_builder);
 
-              for value in json_values.iter().map(|v| v.get(&field.name())) {
+              for value in json_values.iter().map(|v| v.get(field.name())) {
                 if let Some(Value::Array(arr)) = value {
                   let float_builder = list_builder.values();
                   for item in arr {
@@ -283,7 +728,7 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
               let bool_builder = BooleanBuilder::new();
               let mut list_builder = ListBuilder::new(bool_builder);
 
-              for value in json_values.iter().map(|v| v.get(&field.name())) {
+              for value in json_values.iter().map(|v| v.get(field.name())) {
                 if let Some(Value::Array(arr)) = value {
                   let bool_builder = list_builder.values();
                   for item in arr {
@@ -312,56 +757,894 @@ pub fn json_to_arrow(json_values: &[Value]) -> Result<(Vec<ArrayRef>, Schema), B
   Ok((arrays, schema))
 }
 
-#[allow(dead_code)]
-pub enum Granularity {
-  Month,
-  Day,
+// Pick the bucket objects of `file_name` to read for a date range, among the monthly objects at the bucket root
+// (`{file_name}_{YYYY-MM}.parquet`) and the daily objects sunk under a database prefix in either bucket layout
+// (`{db}/{YYYY}/{MM}/{file_name}_{YYYY-MM-DD}[_n].parquet` or `{db}/{file_name}/date={YYYY-MM-DD}/...`): a month with a
+// monthly object is read from it alone, other months from their daily objects. Returns the object keys sorted by date.
+pub fn select_bucket_sources(object_keys: &[String], file_name: &str, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> Vec<String> {
+  let monthly_object = Regex::new(&format!(r"^{}_(\d{{4}}-\d{{2}})\.parquet$", regex::escape(file_name))).unwrap();
+  let daily_object = Regex::new(&format!(
+    r"^[^/]+/(?:\d{{4}}/\d{{2}}|{0}/date=\d{{4}}-\d{{2}}-\d{{2}})/{0}_(\d{{4}}-\d{{2}}-\d{{2}})(?:_\d+)?\.parquet$",
+    regex::escape(file_name)
+  ))
+  .unwrap();
+
+  let mut monthly_objects = Vec::new();
+  let mut daily_objects = Vec::new();
+  for key in object_keys {
+    if let Some(caps) = monthly_object.captures(key) {
+      if let Ok(month_start) = NaiveDate::parse_from_str(&format!("{}-01", &caps[1]), "%Y-%m-%d") {
+        monthly_objects.push((month_start, key.clone()));
+      }
+    } else if let Some(caps) = daily_object.captures(key) {
+      if let Ok(date) = NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d") {
+        daily_objects.push((date, key.clone()));
+      }
+    }
+  }
+
+  let archived_months: HashSet<NaiveDate> = monthly_objects.iter().map(|(month_start, _)| *month_start).collect();
+  let mut sources: Vec<PartitionFile> = monthly_objects
+    .into_iter()
+    .filter(|(month_start, _)| month_overlaps(*month_start, start_date, end_date))
+    .chain(daily_objects.into_iter().filter(|(date, _)| {
+      let month_start = date.with_day(1).unwrap_or(*date);
+      is_within_bounds(*date, start_date, end_date) && !archived_months.contains(&month_start)
+    }))
+    .collect();
+  sources.sort();
+  sources.into_iter().map(|(_, key)| key).collect()
+}
+
+// The partition a data file or bucket object of `file_name` holds, from the last component of its path: `YYYY-MM-DD`
+// for a daily file or one of its `_n` segments, `YYYY-MM` for a monthly file
+pub fn partition_label(path: &str, file_name: &str) -> Option<String> {
+  let partition_file = Regex::new(&format!(
+    r"^{}_(\d{{4}}-\d{{2}}(?:-\d{{2}})?)(?:_\d+)?\.parquet$",
+    regex::escape(file_name)
+  ))
+  .unwrap();
+  let base_name = path.rsplit('/').next().unwrap_or(path);
+  partition_file.captures(base_name).map(|caps| caps[1].to_owned())
+}
+
+// First day of every month overlapping `start_date..=end_date`
+pub fn months_in_range(start_date: NaiveDate, end_date: NaiveDate) -> Vec<NaiveDate> {
+  let mut months = Vec::new();
+  let mut month_start = start_date.with_day(1).unwrap_or(start_date);
+  while month_start <= end_date {
+    months.push(month_start);
+    match month_start.checked_add_months(Months::new(1)) {
+      Some(next_month_start) => month_start = next_month_start,
+      None => break,
+    }
+  }
+  months
+}
+
+// Whether any day of the month starting on `month_start` is within the (possibly open) date range
+pub fn month_overlaps(month_start: NaiveDate, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> bool {
+  let (first_day, last_day) = month_bounds(month_start);
+  start_date.is_none_or(|start| last_day >= start) && end_date.is_none_or(|end| first_day <= end)
+}
+
+// fsync a file (when it still exists) and its directory, so both its content and its creation or removal survive a power loss
+pub fn sync_path(path: &str) -> std::io::Result<()> {
+  if Path::new(path).exists() {
+    fs::File::open(path)?.sync_all()?;
+  }
+  // Directories can only be opened for syncing on unix
+  #[cfg(unix)]
+  if let Some(parent) = Path::new(path).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+    fs::File::open(parent)?.sync_all()?;
+  }
+  Ok(())
+}
+
+// A data file keyed by its partition date (the first day of the month for monthly files)
+pub type PartitionFile = (NaiveDate, String);
+
+// Sort key of a data file: its partition date, then its append segment number (0 for the day's own file), so segment
+// `_10` comes after `_2` where comparing the paths wouldn't, then its path
+pub fn partition_file_order(partition_file: &PartitionFile) -> (NaiveDate, u64, &str) {
+  let (date, file_path) = partition_file;
+  let segment = Path::new(file_path)
+    .file_stem()
+    .and_then(|stem| stem.to_str()?.rsplit_once('_')?.1.parse().ok())
+    .unwrap_or(0);
+  (*date, segment, file_path)
 }
 
-pub fn generate_paths(
-  base_dir: &str,
-  file_name: &str,
-  date_range: HashMap<String, String>,
-  granularity: Granularity,
-  is_s3: bool,
-) -> Result<Vec<String>, ParseError> {
-  let start_date = NaiveDate::parse_from_str(date_range.get("start_date").unwrap(), "%Y-%m-%d")?;
-  let end_date = NaiveDate::parse_from_str(date_range.get("end_date").unwrap(), "%Y-%m-%d")?;
-  let mut current_date = start_date;
+// List every `{file_name}_{YYYY-MM-DD}.parquet` file (and its `_{n}` append segments) in `base_dir`, in `partition_file_order`
+pub fn list_partition_files(base_dir: &str, file_name: &str) -> std::io::Result<Vec<PartitionFile>> {
+  let regx = Regex::new(&format!(r"^{}_(\d{{4}}-\d{{2}}-\d{{2}})(?:_\d+)?\.parquet$", regex::escape(file_name))).unwrap();
 
   let mut file_list = Vec::new();
-  while current_date <= end_date {
-    let path = match granularity {
-      Granularity::Month => format!(
-        "{}{}/{}_{}.parquet",
-        if is_s3 { "s3://" } else { "" },
-        base_dir,
-        file_name,
-        current_date.format("%Y-%m")
-      ),
-      Granularity::Day => format!("{}/{}_{}.parquet", base_dir, file_name, current_date.format("%Y-%m-%d")),
+  for entry in fs::read_dir(base_dir)?.filter_map(|entry| entry.ok()) {
+    let entry_name = entry.file_name().to_string_lossy().to_string();
+    if let Some(caps) = regx.captures(&entry_name) {
+      if let Ok(date) = NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d") {
+        file_list.push((date, format!("{}/{}", base_dir, entry_name)));
+      }
+    }
+  }
+  file_list.sort_by(|a, b| partition_file_order(a).cmp(&partition_file_order(b)));
+  Ok(file_list)
+}
+
+// List every `{file_name}_{YYYY-MM}.parquet` monthly file in `base_dir`, keyed by the first day of its month
+pub fn list_monthly_files(base_dir: &str, file_name: &str) -> std::io::Result<Vec<PartitionFile>> {
+  let regx = Regex::new(&format!(r"^{}_(\d{{4}}-\d{{2}})\.parquet$", regex::escape(file_name))).unwrap();
+
+  let mut file_list = Vec::new();
+  for entry in fs::read_dir(base_dir)?.filter_map(|entry| entry.ok()) {
+    let entry_name = entry.file_name().to_string_lossy().to_string();
+    if let Some(caps) = regx.captures(&entry_name) {
+      if let Ok(month_start) = NaiveDate::parse_from_str(&format!("{}-01", &caps[1]), "%Y-%m-%d") {
+        file_list.push((month_start, format!("{}/{}", base_dir, entry_name)));
+      }
+    }
+  }
+  file_list.sort();
+  Ok(file_list)
+}
+
+// First and last day of the month starting at `month_start`
+pub fn month_bounds(month_start: NaiveDate) -> (NaiveDate, NaiveDate) {
+  let next_month_start = month_start.checked_add_months(Months::new(1)).unwrap_or(NaiveDate::MAX);
+  (month_start, next_month_start.pred_opt().unwrap_or(next_month_start))
+}
+
+// Pick, for every period within the date range, either the monthly rollup or its daily files but never both. A monthly
+// file whose footer records its `timon.min/max_partition_date` bounds replaces the daily files within those bounds; one
+// without bounds spans its whole month and is only used when that month has no daily file in range.
+pub fn select_partition_sources(
+  daily_files: Vec<PartitionFile>,
+  monthly_files: Vec<PartitionFile>,
+  start_date: Option<NaiveDate>,
+  end_date: Option<NaiveDate>,
+) -> (Vec<PartitionFile>, Vec<PartitionFile>) {
+  let monthly_files: Vec<(PartitionFile, Option<(NaiveDate, NaiveDate)>)> = monthly_files
+    .into_iter()
+    .map(|monthly_file| {
+      let recorded_bounds = read_file_metadata(&monthly_file.1).ok().and_then(|file_metadata| {
+        let parse_date = |key: &str| NaiveDate::parse_from_str(file_metadata.get(key)?, "%Y-%m-%d").ok();
+        Some((parse_date("timon.min_partition_date")?, parse_date("timon.max_partition_date")?))
+      });
+      (monthly_file, recorded_bounds)
+    })
+    .filter(|((month_start, _), recorded_bounds)| {
+      let (first_day, last_day) = recorded_bounds.unwrap_or_else(|| month_bounds(*month_start));
+      start_date.is_none_or(|start| last_day >= start) && end_date.is_none_or(|end| first_day <= end)
+    })
+    .collect();
+
+  let daily_files: Vec<PartitionFile> = daily_files
+    .into_iter()
+    .filter(|(date, _)| is_within_bounds(*date, start_date, end_date))
+    .filter(|(date, _)| {
+      !monthly_files
+        .iter()
+        .filter_map(|(_, recorded_bounds)| *recorded_bounds)
+        .any(|(min_date, max_date)| min_date <= *date && *date <= max_date)
+    })
+    .collect();
+
+  let months_with_daily: HashSet<(i32, u32)> = daily_files.iter().map(|(date, _)| (date.year(), date.month())).collect();
+  let monthly_files = monthly_files
+    .into_iter()
+    .filter(|((month_start, _), recorded_bounds)| {
+      recorded_bounds.is_some() || !months_with_daily.contains(&(month_start.year(), month_start.month()))
+    })
+    .map(|(monthly_file, _)| monthly_file)
+    .collect();
+
+  (daily_files, monthly_files)
+}
+
+// Find the first free `{file_name}_{date}_{n}.parquet` segment path for an append-only table or an upsert
+pub fn next_segment_path(base_dir: &str, file_name: &str, date: &str) -> String {
+  let mut segment = 1;
+  loop {
+    let segment_path = format!("{}/{}_{}_{}.parquet", base_dir, file_name, date, segment);
+    if !Path::new(&segment_path).exists() {
+      return segment_path;
+    }
+    segment += 1;
+  }
+}
+
+// Build the `timon.*` key-value metadata embedded in the footer of every Parquet file Timon writes. The schema hash is
+// the SHA-256 of the declared schema, so it's the same across processes and versions. The partition date bounds are
+// the days the rows' `partition_column` values fall on, or the file's `partition_date` without such a column or values.
+pub fn build_file_metadata(
+  db_name: &str,
+  table_name: &str,
+  table_schema: &Value,
+  rows: &[Value],
+  partition_column: Option<&str>,
+  partition_date: &str,
+) -> Vec<KeyValue> {
+  let schema_hash = ring::digest::digest(&ring::digest::SHA256, table_schema.to_string().as_bytes());
+  let row_days: Vec<NaiveDate> = partition_column
+    .map(|column| rows.iter().filter_map(|row| partition_day_of(row.get(column)?)).collect())
+    .unwrap_or_default();
+  let format_day = |day: Option<&NaiveDate>| day.map_or_else(|| partition_date.to_string(), |day| day.format("%Y-%m-%d").to_string());
+
+  [
+    ("timon.version", env!("CARGO_PKG_VERSION").to_string()),
+    ("timon.db_name", db_name.to_string()),
+    ("timon.table_name", table_name.to_string()),
+    ("timon.schema_hash", hex::encode(schema_hash.as_ref())),
+    ("timon.row_count", rows.len().to_string()),
+    ("timon.min_partition_date", format_day(row_days.iter().min())),
+    ("timon.max_partition_date", format_day(row_days.iter().max())),
+  ]
+  .into_iter()
+  .map(|(key, value)| KeyValue::new(key.to_string(), value))
+  .collect()
+}
+
+// Read back the `timon.*` key-value metadata of a Parquet file from its footer
+pub fn read_file_metadata(file_path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+  let reader = SerializedFileReader::new(fs::File::open(file_path)?)?;
+  let key_values = reader.metadata().file_metadata().key_value_metadata().cloned().unwrap_or_default();
+  Ok(
+    key_values
+      .into_iter()
+      .filter(|kv| kv.key.starts_with("timon."))
+      .map(|kv| (kv.key, kv.value.unwrap_or_default()))
+      .collect(),
+  )
+}
+
+// Read the row count from a Parquet file footer without scanning its data
+pub fn read_parquet_row_count(file_path: &str) -> Result<i64, Box<dyn Error>> {
+  let reader = SerializedFileReader::new(fs::File::open(file_path)?)?;
+  Ok(reader.metadata().file_metadata().num_rows())
+}
+
+// Resolve the relative keys of a date range against today's date: `{"last": "7d"}` covers the 7 days ending today
+// (units `d`, `w`, `mo`, `y`), `{"since": "2024-01-01"}` runs from that date through today. Days are UTC days unless
+// `utc_offset` (e.g. "+05:30") gives the caller's zone, in which case the local days are widened to the UTC partition
+// days they overlap, since partition files are named by UTC date. Explicit `start_date`/`end_date` keys are UTC
+// partition dates, kept as they are and taking precedence over the resolved ones.
+pub fn resolve_relative_date_range(date_range: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+  resolve_relative_date_range_at(date_range, Utc::now())
+}
+
+fn resolve_relative_date_range_at(date_range: &HashMap<String, String>, now: DateTime<Utc>) -> Result<HashMap<String, String>, String> {
+  let offset = match date_range.get("utc_offset") {
+    Some(utc_offset) => utc_offset
+      .trim()
+      .parse::<FixedOffset>()
+      .map_err(|e| format!("Invalid utc_offset '{}', expected e.g. +05:30: {}", utc_offset, e))?,
+    None => FixedOffset::east_opt(0).unwrap(),
+  };
+  let today = now.with_timezone(&offset).date_naive();
+  // The UTC day of the first or last instant of a local day
+  let partition_day = |local_day: NaiveDate, time: NaiveTime| (local_day.and_time(time) - offset).format("%Y-%m-%d").to_string();
+  let (first_instant, last_instant) = (NaiveTime::MIN, NaiveTime::from_hms_milli_opt(23, 59, 59, 999).unwrap());
+  let mut resolved = HashMap::new();
+
+  if let Some(last) = date_range.get("last") {
+    let invalid = || format!("Invalid relative range '{}', expected e.g. 7d, 2w, 1mo or 1y", last);
+    let out_of_bounds = || format!("Relative range '{}' is out of bounds", last);
+    let caps = Regex::new(r"^(\d+)(d|w|mo|y)$").unwrap().captures(last.trim()).ok_or_else(invalid)?;
+    // A zero amount would be an empty window, starting the day after today
+    let amount: u32 = caps[1].parse().ok().filter(|amount| *amount > 0).ok_or_else(invalid)?;
+    let window_start = match &caps[2] {
+      "d" => today.checked_sub_days(Days::new(amount as u64)),
+      "w" => today.checked_sub_days(Days::new(amount as u64 * 7)),
+      "mo" => today.checked_sub_months(Months::new(amount)),
+      _ => today.checked_sub_months(Months::new(amount.checked_mul(12).ok_or_else(out_of_bounds)?)),
+    }
+    .ok_or_else(out_of_bounds)?;
+    // The window ends today inclusive, so it starts the day after `today - interval`
+    let start_date = window_start.succ_opt().unwrap_or(window_start);
+    resolved.insert("start_date".to_string(), partition_day(start_date, first_instant));
+    resolved.insert("end_date".to_string(), partition_day(today, last_instant));
+  } else if let Some(since) = date_range.get("since") {
+    let since = NaiveDate::parse_from_str(since, "%Y-%m-%d").map_err(|e| format!("Invalid since date '{}': {}", since, e))?;
+    resolved.insert("start_date".to_string(), partition_day(since, first_instant));
+    resolved.insert("end_date".to_string(), partition_day(today, last_instant));
+  }
+
+  for key in ["start_date", "end_date"] {
+    if let Some(value) = date_range.get(key) {
+      resolved.insert(key.to_string(), value.clone());
+    }
+  }
+  Ok(resolved)
+}
+
+// Parse the optional `start_date`/`end_date` bounds of a date range; a missing key or the `*` wildcard leaves that side open
+pub fn parse_date_bounds(date_range: Option<&HashMap<String, String>>) -> Result<(Option<NaiveDate>, Option<NaiveDate>), ParseError> {
+  let parse_bound = |key: &str| -> Result<Option<NaiveDate>, ParseError> {
+    match date_range.and_then(|range| range.get(key)) {
+      Some(value) if value != "*" => NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Some),
+      _ => Ok(None),
+    }
+  };
+  Ok((parse_bound("start_date")?, parse_bound("end_date")?))
+}
+
+// The UTC day a partition column value falls on: a string starting with `YYYY-MM-DD` or epoch milliseconds
+pub fn partition_day_of(value: &Value) -> Option<NaiveDate> {
+  match value {
+    Value::String(text) => NaiveDate::parse_from_str(text.get(..10)?, "%Y-%m-%d").ok(),
+    Value::Number(number) => DateTime::from_timestamp_millis(number.as_i64()?).map(|datetime| datetime.date_naive()),
+    _ => None,
+  }
+}
+
+pub fn is_within_bounds(date: NaiveDate, start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> bool {
+  start_date.is_none_or(|start| date >= start) && end_date.is_none_or(|end| date <= end)
+}
+
+pub struct PartitionOrder {
+  pub projection: String,
+  pub column: String,
+  pub descending: bool,
+  pub secondary_keys: Vec<(String, bool)>, // Sort keys after the partition column as written (quotes kept), with whether each is descending
+  pub limit: usize,
+}
+
+impl PartitionOrder {
+  // The ORDER BY list each day is sorted with
+  pub fn order_by(&self) -> String {
+    std::iter::once((&self.column, self.descending))
+      .chain(self.secondary_keys.iter().map(|(column, descending)| (column, *descending)))
+      .map(|(column, descending)| format!("{} {}", column, if descending { "DESC" } else { "ASC" }))
+      .collect::<Vec<_>>()
+      .join(", ")
+  }
+}
+
+// Match `SELECT <columns> FROM <table> ORDER BY <partition_column> [ASC|DESC][, <column> [ASC|DESC]]... LIMIT <n>`, the
+// only shape whose ordering can be answered day by day: the partition column orders the days, and the secondary keys
+// only break ties between rows of the same day. Anything else (filters, aggregates, a leading key other than the
+// partition column, expressions or NULLS FIRST/LAST) returns None and takes the full sort.
+pub fn parse_partition_order(sql_query: &str, partition_column: &str) -> Option<PartitionOrder> {
+  let caps = Regex::new(
+    r##"(?is)^\s*SELECT\s+(?P<projection>[\w\s,*`"]+?)\s+FROM\s+[`"]?\w+[`"]?\s+ORDER\s+BY\s+(?P<keys>[`"]?\w+[`"]?(?:\s+(?:ASC|DESC))?(?:\s*,\s*[`"]?\w+[`"]?(?:\s+(?:ASC|DESC))?)*)\s+LIMIT\s+(?P<limit>\d+)\s*;?\s*$"##,
+  )
+  .unwrap()
+  .captures(sql_query)?;
+
+  let key_regex = Regex::new(r##"(?i)^(?P<key>[`"]?(?P<column>\w+)[`"]?)(?:\s+(?P<direction>ASC|DESC))?$"##).unwrap();
+  let mut sort_keys = caps["keys"].split(',').map(|key| {
+    let key_caps = key_regex.captures(key.trim())?;
+    let descending = key_caps.name("direction").is_some_and(|m| m.as_str().eq_ignore_ascii_case("DESC"));
+    Some((key_caps["key"].to_string(), key_caps["column"].to_string(), descending))
+  });
+  let (_, column, descending) = sort_keys.next()??;
+  if column != *partition_column {
+    return None;
+  }
+  let secondary_keys = sort_keys.map(|key| key.map(|(key, _, descending)| (key, descending)));
+  Some(PartitionOrder {
+    projection: caps["projection"].to_string(),
+    column,
+    descending,
+    secondary_keys: secondary_keys.collect::<Option<Vec<_>>>()?,
+    limit: caps["limit"].parse().ok()?,
+  })
+}
+
+// Narrow the partition days a query can touch from comparisons of the partition column with date literals in its WHERE
+// clause (`day >= '2024-01-05'`, `day BETWEEN '2024-01-01' AND '2024-01-03'`, ...). Only a single SELECT whose WHERE is
+// a plain conjunction is considered, so that a day outside the bounds provably has no matching row; anything else
+// (OR, NOT, joins, subqueries, unions) leaves the bounds open.
+pub fn parse_partition_bounds(sql_query: &str, partition_column: &str) -> (Option<NaiveDate>, Option<NaiveDate>) {
+  let is_simple = Regex::new(r"(?i)\bSELECT\b").unwrap().find_iter(sql_query).count() == 1
+    && !Regex::new(r"(?i)\b(?:JOIN|UNION|INTERSECT|EXCEPT)\b").unwrap().is_match(sql_query);
+  let where_clause = Regex::new(r"(?is)\bWHERE\b(?P<filter>.*?)(?:\bGROUP\s+BY\b|\bHAVING\b|\bORDER\s+BY\b|\bLIMIT\b|;|$)")
+    .unwrap()
+    .captures(sql_query)
+    .map(|caps| caps["filter"].to_string());
+  let Some(filter) = where_clause.filter(|filter| is_simple && !Regex::new(r"(?i)\b(?:OR|NOT)\b").unwrap().is_match(filter)) else {
+    return (None, None);
+  };
+
+  // The column as a whole identifier, bare or in balanced quotes, so `day` doesn't match inside `birthday`
+  let escaped_column = regex::escape(partition_column);
+  let column = format!(r#"(?:^|[^\w"`])(?:{0}\b|"{0}"|`{0}`)"#, escaped_column);
+  // A literal is a date, optionally followed by a time that still falls on that day
+  let literal = r"'(\d{4}-\d{2}-\d{2})([^']*)'";
+  let (mut start_date, mut end_date): (Option<NaiveDate>, Option<NaiveDate>) = (None, None);
+  let mut tighten = |start: Option<NaiveDate>, end: Option<NaiveDate>| {
+    start_date = start_date.max(start);
+    end_date = match (end_date, end) {
+      (Some(current), Some(end)) => Some(current.min(end)),
+      (current, end) => current.or(end),
     };
-    file_list.push(path);
-    current_date = match granularity {
-      Granularity::Month => current_date
-        .with_month(current_date.month() % 12 + 1)
-        .unwrap_or_else(|| NaiveDate::from_ymd_opt(current_date.year() + 1, 1, 1).unwrap()),
-      Granularity::Day => current_date.succ_opt().unwrap(),
+  };
+
+  let between = Regex::new(&format!(r"(?i){}\s+BETWEEN\s+{}\s+AND\s+{}", column, literal, literal)).unwrap();
+  for caps in between.captures_iter(&filter) {
+    tighten(
+      NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d").ok(),
+      NaiveDate::parse_from_str(&caps[3], "%Y-%m-%d").ok(),
+    );
+  }
+  let comparison = Regex::new(&format!(r"{}\s*(>=|<=|=|>|<)\s*{}", column, literal)).unwrap();
+  for caps in comparison.captures_iter(&filter) {
+    let Ok(date) = NaiveDate::parse_from_str(&caps[2], "%Y-%m-%d") else {
+      continue;
     };
+    match &caps[1] {
+      "=" => tighten(Some(date), Some(date)),
+      ">" | ">=" => tighten(Some(date), None),
+      // `< '2024-01-05'` (midnight) excludes the whole day, `< '2024-01-05 12:00'` doesn't
+      "<" if caps[3].is_empty() => tighten(None, date.pred_opt()),
+      _ => tighten(None, Some(date)),
+    }
   }
-  Ok(file_list)
+  (start_date, end_date)
+}
+
+// Register the queried table both under the lowercase name DataFusion resolves an unquoted `FROM Events` to and under
+// the exact name a quoted `FROM "Events"` asks for
+pub fn register_queried_table(ctx: &SessionContext, table_name: &str, table: Arc<dyn TableProvider>) -> DataFusionResult<()> {
+  let lowercase_name = table_name.to_lowercase();
+  if lowercase_name != table_name {
+    ctx.register_table(TableReference::bare(lowercase_name), table.clone())?;
+  }
+  ctx.register_table(TableReference::bare(table_name), table)?;
+  Ok(())
+}
+
+// The first two field names that only differ by case (e.g. `Temp` and `temp`), which unquoted SQL can't tell apart
+pub fn find_case_insensitive_duplicate<'a>(field_names: impl IntoIterator<Item = &'a String>) -> Option<(String, String)> {
+  let mut seen: HashMap<String, &String> = HashMap::new();
+  for field_name in field_names {
+    if let Some(previous) = seen.insert(field_name.to_lowercase(), field_name) {
+      if previous != field_name {
+        return Some((previous.clone(), field_name.clone()));
+      }
+    }
+  }
+  None
 }
 
 pub fn extract_table_name(sql_query: &str) -> String {
-  Regex::new(r##"(?:FROM|JOIN)\s+[`\"]?(\w+)[`\"]?"##)
-    .unwrap()
-    .captures_iter(sql_query)
-    .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
-    .nth(0)
-    .unwrap_or_else(|| {
-      eprintln!("No table name found in the SQL query.");
-      String::new()
+  extract_table_names(sql_query).into_iter().next().unwrap_or_else(|| {
+    eprintln!("No table name found in the SQL query.");
+    String::new()
+  })
+}
+
+// Every distinct name following a FROM or JOIN, in order of appearance; CTE names are included like table names
+pub fn extract_table_names(sql_query: &str) -> Vec<String> {
+  let mut table_names: Vec<String> = Vec::new();
+  for cap in Regex::new(r##"(?:FROM|JOIN)\s+[`\"]?(\w+)[`\"]?"##).unwrap().captures_iter(sql_query) {
+    let table_name = cap[1].to_string();
+    if !table_names.contains(&table_name) {
+      table_names.push(table_name);
+    }
+  }
+  table_names
+}
+
+// Reject anything but a single read-only query before it reaches `ctx.sql`, as the SQL may come from less-trusted UI
+// input: SET, SHOW, EXPLAIN, DDL and DML statements fail, and so does `SELECT ... INTO` (which creates a table).
+// Qualified table names such as `information_schema.tables` fail too, since a query only reads the tables of its database.
+pub fn validate_read_only_sql(sql_query: &str) -> DataFusionResult<()> {
+  let mut statements = DFParser::parse_sql_with_dialect(sql_query, &GenericDialect {})?;
+  let statement = match (statements.pop_front(), statements.len()) {
+    (Some(statement), 0) => statement,
+    (Some(_), _) => return Err(DataFusionError::Plan("Only a single SQL statement can be queried".to_owned())),
+    (None, _) => return Err(DataFusionError::Plan("The SQL query is empty".to_owned())),
+  };
+  let query = match statement {
+    DFStatement::Statement(statement) => match *statement {
+      SQLStatement::Query(query) => query,
+      statement => return Err(DataFusionError::Plan(format!("Only SELECT queries are allowed, got: {}", statement))),
+    },
+    statement => return Err(DataFusionError::Plan(format!("Only SELECT queries are allowed, got: {}", statement))),
+  };
+  if matches!(query.body.as_ref(), SetExpr::Select(select) if select.into.is_some()) {
+    return Err(DataFusionError::Plan(
+      "SELECT ... INTO is not allowed, only reading queries are".to_owned(),
+    ));
+  }
+  let qualified = visit_relations(&query, |relation| match relation.0.len() {
+    1 => ControlFlow::Continue(()),
+    _ => ControlFlow::Break(relation.to_string()),
+  });
+  if let ControlFlow::Break(relation) = qualified {
+    return Err(DataFusionError::Plan(format!(
+      "Table '{}' is outside the queried database, only unqualified table names are allowed",
+      relation
+    )));
+  }
+  Ok(())
+}
+
+// Derive a table schema from sample records using `json_to_arrow`'s type inference.
+// The inferred schema never marks fields as `required` or `unique`.
+pub fn infer_schema_from_json(json_values: &[Value]) -> Result<Value, Box<dyn Error>> {
+  let (_, arrow_schema) = json_to_arrow(json_values)?;
+  arrow_schema_to_table_schema(&arrow_schema)
+}
+
+// Describe an Arrow schema (e.g. of a Parquet file) as a table schema with every field optional
+pub fn arrow_schema_to_table_schema(arrow_schema: &Schema) -> Result<Value, Box<dyn Error>> {
+  let mut schema = serde_json::Map::new();
+  for field in arrow_schema.fields() {
+    let field_type = match field.data_type() {
+      DataType::Int64 => "int",
+      DataType::Float64 => "int|float", // JSON serializers commonly drop the fraction of whole floats
+      DataType::Utf8 => "string",
+      DataType::Boolean => "bool",
+      DataType::List(_) => "array",
+      DataType::Binary => "binary",
+      data_type => return Err(format!("Cannot infer a schema type for field '{}' ({:?})", field.name(), data_type).into()),
+    };
+    schema.insert(field.name().clone(), json!({ "type": field_type, "required": false }));
+  }
+
+  Ok(Value::Object(schema))
+}
+
+// Union an inferred schema into a dynamic table's schema: new fields are added and a field seen with another type
+// accepts both (e.g. "int" then "string" gives "int|string")
+pub fn merge_json_schemas(existing: &Value, inferred: &Value) -> Value {
+  let mut merged = existing.as_object().cloned().unwrap_or_default();
+  for (field_name, rules) in inferred.as_object().into_iter().flatten() {
+    let inferred_type = rules.get("type").and_then(Value::as_str).unwrap_or_default();
+    let field_type = match merged.get(field_name).and_then(|rules| rules.get("type")).and_then(Value::as_str) {
+      Some(current_type) => {
+        let mut types: Vec<&str> = current_type.split('|').collect();
+        for new_type in inferred_type.split('|') {
+          if !types.contains(&new_type) {
+            types.push(new_type);
+          }
+        }
+        types.join("|")
+      }
+      None => inferred_type.to_owned(),
+    };
+    merged.insert(field_name.clone(), json!({ "type": field_type, "required": false }));
+  }
+  Value::Object(merged)
+}
+
+// Union the Arrow schemas of a dynamic table's files so every file is read with the same columns; a column missing
+// from a file reads as null, and one stored with different types is widened (Int64 with Float64 to Float64, else Utf8)
+pub fn merge_arrow_schemas(schemas: &[Schema]) -> Schema {
+  let mut fields: Vec<ArrowField> = Vec::new();
+  for field in schemas.iter().flat_map(|schema| schema.fields().iter()) {
+    match fields.iter_mut().find(|existing| existing.name() == field.name()) {
+      Some(existing) if existing.data_type() != field.data_type() => {
+        let widened = match (existing.data_type(), field.data_type()) {
+          (DataType::Int64, DataType::Float64) | (DataType::Float64, DataType::Int64) => DataType::Float64,
+          _ => DataType::Utf8,
+        };
+        *existing = ArrowField::new(field.name(), widened, true);
+      }
+      Some(_) => {}
+      None => fields.push(ArrowField::new(field.name(), field.data_type().clone(), true)),
+    }
+  }
+  Schema::new(fields)
+}
+
+// Force the listed columns of a read schema to a table schema type (`string`, `int`, `uint`, `float`, `decimal`, `bool`,
+// `timestamp`, `object` or `binary`), adding the ones missing from every file, so files that stored a column with
+// different types are all cast to the same one
+pub fn override_arrow_schema(schema: &Schema, overrides: &BTreeMap<String, String>) -> Result<Schema, String> {
+  let mut fields: Vec<ArrowField> = schema.fields().iter().map(|field| field.as_ref().clone()).collect();
+  for (column, type_name) in overrides {
+    let data_type = match type_name.as_str() {
+      "string" | "object" => DataType::Utf8,
+      "int" => DataType::Int64,
+      "uint" => DataType::UInt64,
+      "float" | "decimal" => DataType::Float64,
+      "bool" => DataType::Boolean,
+      "timestamp" => DataType::Timestamp(TimeUnit::Millisecond, None),
+      "binary" => DataType::Binary,
+      _ => return Err(format!("Schema override of column '{}' has an unsupported type '{}'", column, type_name)),
+    };
+    let field = ArrowField::new(column, data_type, true);
+    match fields.iter_mut().find(|existing| existing.name() == column) {
+      Some(existing) => *existing = field,
+      None => fields.push(field),
+    }
+  }
+  Ok(Schema::new(fields))
+}
+
+pub fn read_parquet_arrow_schema(file_path: &str) -> Result<Schema, Box<dyn Error>> {
+  Ok(read_parquet_schema(file_path)?.arrow_schema)
+}
+
+// A Parquet file's schema and row count as recorded in its footer
+#[derive(Serialize, Debug, Clone)]
+pub struct ParquetFileSchema {
+  pub rows: i64,
+  pub columns: Vec<ParquetColumn>,
+  #[serde(skip)]
+  pub arrow_schema: Schema, // The schema the file is read with
+}
+
+// A leaf column of a Parquet file; list items are named by their path, e.g. `tags.list.item`
+#[derive(Serialize, Debug, Clone)]
+pub struct ParquetColumn {
+  pub name: String,
+  pub physical_type: String,        // e.g. "INT64" or "BYTE_ARRAY"
+  pub logical_type: Option<String>, // e.g. "String", unset for plain numbers and booleans
+  pub repetition: String,           // "REQUIRED", "OPTIONAL" or "REPEATED"
+}
+
+// Read a Parquet file's schema and row count from its footer, without a SessionContext or reading any data
+pub fn read_parquet_schema(file_path: &str) -> Result<ParquetFileSchema, Box<dyn Error>> {
+  let reader = SerializedFileReader::new(fs::File::open(file_path)?)?;
+  let file_metadata = reader.metadata().file_metadata();
+  let schema_descr = file_metadata.schema_descr();
+
+  let columns = schema_descr
+    .columns()
+    .iter()
+    .map(|column| ParquetColumn {
+      name: column.path().string(),
+      physical_type: column.physical_type().to_string(),
+      logical_type: column.logical_type().map(|logical_type| format!("{:?}", logical_type)),
+      repetition: column.self_type().get_basic_info().repetition().to_string(),
+    })
+    .collect();
+  let arrow_schema = parquet_to_arrow_schema(schema_descr, file_metadata.key_value_metadata())?;
+
+  Ok(ParquetFileSchema {
+    rows: file_metadata.num_rows(),
+    columns,
+    arrow_schema,
+  })
+}
+
+// Write the integers sent for float (or decimal) fields as floats, so a batch of integer-valued floats doesn't produce
+// an Int64 column; `int|float` fields keep their integers unless they don't fit an i64
+pub fn coerce_declared_floats(schema: &Value, json_values: &mut [Value]) {
+  let Some(schema_obj) = schema.as_object() else {
+    return;
+  };
+  let float_fields: Vec<(&String, bool)> = schema_obj
+    .iter()
+    .filter_map(|(field_name, rules)| {
+      let types: Vec<&str> = rules.get("type")?.as_str()?.split('|').collect();
+      (types.contains(&"float") || types.contains(&"decimal")).then_some((field_name, types.contains(&"int")))
+    })
+    .collect();
+  for record in json_values.iter_mut().filter_map(Value::as_object_mut) {
+    for (field_name, takes_int) in &float_fields {
+      if let Some(value) = record.get_mut(*field_name) {
+        let is_integer = value.is_i64() || value.is_u64();
+        if is_integer && (!takes_int || value.as_i64().is_none()) {
+          if let Some(float) = value.as_f64().and_then(serde_json::Number::from_f64) {
+            *value = Value::Number(float);
+          }
+        }
+      }
+    }
+  }
+}
+
+// Write the values of `object` fields as their JSON text, since files have no column type for arbitrary objects
+pub fn stringify_declared_objects(schema: &Value, json_values: &mut [Value]) {
+  let Some(schema_obj) = schema.as_object() else {
+    return;
+  };
+  let object_fields: Vec<&String> = schema_obj
+    .iter()
+    .filter(|(_, rules)| {
+      rules
+        .get("type")
+        .and_then(Value::as_str)
+        .is_some_and(|t| t.split('|').any(|t| t == "object"))
+    })
+    .map(|(field_name, _)| field_name)
+    .collect();
+  for record in json_values.iter_mut().filter_map(Value::as_object_mut) {
+    for field_name in &object_fields {
+      if let Some(value) = record.get_mut(*field_name).filter(|value| value.is_object()) {
+        *value = Value::String(value.to_string());
+      }
+    }
+  }
+}
+
+// Formats `timestamp` fields accept besides epoch milliseconds and RFC 3339, when a table lists none; read as UTC.
+// `%.f` also matches a missing fraction.
+pub const DEFAULT_TIMESTAMP_FORMATS: [&str; 3] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y.%m.%d %H:%M:%S%.f"];
+
+// Read a timestamp string as epoch milliseconds, trying RFC 3339 (which carries its offset) then each of `formats` as UTC
+pub fn parse_timestamp_millis(value: &str, formats: &[String]) -> Option<i64> {
+  if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+    return Some(date_time.timestamp_millis());
+  }
+  formats
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .map(|date_time| date_time.and_utc().timestamp_millis())
+}
+
+// Write the values of `timestamp` fields as epoch milliseconds, so producers can send integers, RFC 3339 strings or
+// strings in one of the table's `formats` (the defaults when empty). A string that matches none is rejected, unless
+// the field also takes strings.
+pub fn normalize_declared_timestamps(schema: &Value, formats: &[String], json_values: &mut [Value]) -> Result<(), String> {
+  let Some(schema_obj) = schema.as_object() else {
+    return Ok(());
+  };
+  let timestamp_fields: Vec<(&String, bool)> = schema_obj
+    .iter()
+    .filter_map(|(field_name, rules)| {
+      let types: Vec<&str> = rules.get("type")?.as_str()?.split('|').collect();
+      types.contains(&"timestamp").then_some((field_name, types.contains(&"string")))
     })
+    .collect();
+  if timestamp_fields.is_empty() {
+    return Ok(());
+  }
+
+  let formats: Vec<String> = if formats.is_empty() {
+    DEFAULT_TIMESTAMP_FORMATS.iter().map(|format| format.to_string()).collect()
+  } else {
+    formats.to_vec()
+  };
+  for record in json_values.iter_mut().filter_map(Value::as_object_mut) {
+    for (field_name, takes_string) in &timestamp_fields {
+      let Some(Value::String(text)) = record.get(*field_name) else {
+        continue;
+      };
+      match parse_timestamp_millis(text, &formats) {
+        Some(millis) => {
+          record.insert(field_name.to_string(), json!(millis));
+        }
+        None if *takes_string => {}
+        None => {
+          return Err(format!(
+            "Field '{}' has an invalid timestamp '{}': expected epoch milliseconds, an RFC 3339 string or one of the formats '{}'",
+            field_name,
+            text,
+            formats.join("', '")
+          ))
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+// Convert records like `json_to_arrow`, storing the fields the table schema declares as `timestamp` as millisecond
+// timestamps instead of integers, and the base64 strings of `binary` fields as the bytes they encode
+pub fn json_to_arrow_with_schema(json_values: &[Value], table_schema: &Value) -> Result<(Vec<ArrayRef>, Schema), Box<dyn Error>> {
+  let (arrays, schema) = json_to_arrow_with_floats(json_values, &declared_float_fields(table_schema))?;
+  store_declared_types(arrays, &schema, table_schema)
+}
+
+// Convert records like `json_to_arrow_with_schema`, with the column types `source` picks. Rows are decoded straight into
+// arrays of those types without scanning them first, except for the columns whose type can't be told without the rows
+// (a declared type union other than `int|float`, or a first row's null or empty array), which are inferred as usual.
+pub fn json_to_arrow_with_source(
+  json_values: &[Value],
+  table_schema: &Value,
+  source: SchemaSource,
+) -> Result<(Vec<ArrayRef>, Schema), Box<dyn Error>> {
+  if source == SchemaSource::InferAll {
+    return json_to_arrow_with_schema(json_values, table_schema);
+  }
+  let first_row = json_values.first().ok_or("No data to write")?;
+  let float_fields = declared_float_fields(table_schema);
+  let mut inferred_schema = None;
+  let mut inferred_field = |field_name: &str| -> Result<Option<ArrowField>, Box<dyn Error>> {
+    if inferred_schema.is_none() {
+      inferred_schema = Some(json_to_arrow_with_floats(json_values, &float_fields)?.1);
+    }
+    Ok(
+      inferred_schema
+        .as_ref()
+        .and_then(|schema| schema.field_with_name(field_name).ok())
+        .cloned(),
+    )
+  };
+
+  let mut fields = Vec::new();
+  if source == SchemaSource::Declared {
+    for (field_name, rules) in table_schema.as_object().ok_or("Schema should be a JSON object")? {
+      let required = rules.get("required").and_then(Value::as_bool) == Some(true);
+      match declared_decode_type(rules) {
+        Some(data_type) => fields.push(ArrowField::new(field_name, data_type, !required)),
+        None => fields.extend(inferred_field(field_name)?),
+      }
+    }
+  } else {
+    let (_, first_row_schema) = json_to_arrow_with_floats(std::slice::from_ref(first_row), &float_fields)?;
+    for field in first_row_schema.fields() {
+      match field.data_type() {
+        DataType::Null => fields.extend(inferred_field(field.name())?),
+        DataType::List(item) if item.data_type() == &DataType::Null => fields.extend(inferred_field(field.name())?),
+        // The decoder would truncate the floats of later rows in a column that's an integer in the first row
+        DataType::Int64 => match json_values.iter().position(|row| row.get(field.name()).is_some_and(Value::is_f64)) {
+          Some(index) => return Err(format!("Field '{}' is an integer in the first row but a float in row {}", field.name(), index).into()),
+          None => fields.push(field.as_ref().clone().with_nullable(true)),
+        },
+        _ => fields.push(field.as_ref().clone().with_nullable(true)),
+      }
+    }
+  }
+
+  let decode_schema = Arc::new(Schema::new(fields));
+  let mut decoder = ReaderBuilder::new(decode_schema.clone())
+    .with_batch_size(json_values.len())
+    .with_strict_mode(true)
+    .build_decoder()?;
+  decoder.serialize(json_values)?;
+  let batch = decoder.flush()?.ok_or("No data to write")?;
+  store_declared_types(batch.columns().to_vec(), &decode_schema, table_schema)
+}
+
+// The Arrow type `SchemaSource::Declared` decodes a field with, before its `timestamp` or `binary` values are converted;
+// unset for a type union other than `int|float` and for an array without a plain `items` type
+fn declared_decode_type(rules: &Value) -> Option<DataType> {
+  match rules.get("type")?.as_str()? {
+    "string" | "object" | "binary" => Some(DataType::Utf8),
+    "int" | "uint" | "timestamp" => Some(DataType::Int64),
+    "float" | "decimal" | "int|float" | "float|int" => Some(DataType::Float64),
+    "bool" => Some(DataType::Boolean),
+    "array" => {
+      let item_type = match rules.get("items")?.as_str()? {
+        "string" => DataType::Utf8,
+        "int" => DataType::Int64,
+        "float" | "int|float" | "float|int" => DataType::Float64,
+        "bool" => DataType::Boolean,
+        _ => return None,
+      };
+      Some(DataType::List(Arc::new(ArrowField::new("item", item_type, true))))
+    }
+    _ => None,
+  }
+}
+
+// Store the fields the table schema declares as `timestamp` as millisecond timestamps instead of integers, and the
+// base64 strings of `binary` fields as the bytes they encode
+fn store_declared_types(mut arrays: Vec<ArrayRef>, schema: &Schema, table_schema: &Value) -> Result<(Vec<ArrayRef>, Schema), Box<dyn Error>> {
+  let mut fields = Vec::with_capacity(schema.fields().len());
+  for (array, field) in arrays.iter_mut().zip(schema.fields()) {
+    let field_type = table_schema.get(field.name()).and_then(|rules| rules.get("type")).and_then(Value::as_str);
+    if field_type == Some("timestamp") && field.data_type() == &DataType::Int64 {
+      *array = cast(array, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+      fields.push(ArrowField::new(field.name(), array.data_type().clone(), field.is_nullable()));
+    } else if field_type == Some("binary") && field.data_type() == &DataType::Utf8 {
+      let decoded = array
+        .as_string::<i32>()
+        .iter()
+        .map(|text| text.map(|text| general_purpose::STANDARD.decode(text)).transpose())
+        .collect::<Result<Vec<Option<Vec<u8>>>, _>>()?;
+      *array = Arc::new(BinaryArray::from_iter(decoded));
+      fields.push(ArrowField::new(field.name(), DataType::Binary, field.is_nullable()));
+    } else {
+      fields.push(field.as_ref().clone());
+    }
+  }
+  Ok((arrays, Schema::new_with_metadata(fields, schema.metadata().clone())))
+}
+
+// Identify a row by the values of `fields`, as the unique-field deduplication and the insert modes compare rows
+pub fn row_key(record: &Value, fields: &[String]) -> String {
+  fields
+    .iter()
+    .map(|field| record.get(field).map(|v| v.to_string()).unwrap_or_default())
+    .collect::<Vec<String>>()
+    .join("-")
+}
+
+// The `row_key` of a stored row, or `None` when it has no value for one of the key fields and so can't match any key
+pub fn stored_row_key(record: &Value, fields: &[String]) -> Option<String> {
+  fields
+    .iter()
+    .all(|field| record.get(field).is_some_and(|value| !value.is_null()))
+    .then(|| row_key(record, fields))
 }
 
 pub fn get_unique_fields(schema: Value) -> Result<Vec<String>, Box<dyn Error>> {
@@ -379,3 +1662,303 @@ pub fn get_unique_fields(schema: Value) -> Result<Vec<String>, Box<dyn Error>> {
 
   Ok(unique_fields)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+  }
+
+  #[test]
+  fn partition_bounds_match_the_whole_column_name() {
+    let bounds = parse_partition_bounds("SELECT * FROM t WHERE birthday = '2024-01-01'", "day");
+    assert_eq!(bounds, (None, None));
+    let bounds = parse_partition_bounds("SELECT * FROM t WHERE day_of_week = '2024-01-01'", "day");
+    assert_eq!(bounds, (None, None));
+    let bounds = parse_partition_bounds("SELECT * FROM t WHERE day = '2024-01-01'", "day");
+    assert_eq!(bounds, (date("2024-01-01"), date("2024-01-01")));
+  }
+
+  #[test]
+  fn partition_bounds_strip_balanced_quotes_only() {
+    let sql = r#"SELECT * FROM t WHERE "day" >= '2024-01-02' AND `day` <= '2024-01-05'"#;
+    assert_eq!(parse_partition_bounds(sql, "day"), (date("2024-01-02"), date("2024-01-05")));
+    let sql = r#"SELECT * FROM t WHERE "day >= '2024-01-02'"#;
+    assert_eq!(parse_partition_bounds(sql, "day"), (None, None));
+  }
+
+  #[test]
+  fn partition_bounds_from_between_and_comparisons() {
+    let sql = "SELECT * FROM t WHERE day BETWEEN '2024-01-01' AND '2024-01-10' AND day < '2024-01-05' LIMIT 10";
+    assert_eq!(parse_partition_bounds(sql, "day"), (date("2024-01-01"), date("2024-01-04")));
+    let sql = "SELECT * FROM t WHERE day = '2024-01-01' OR day = '2024-01-09'";
+    assert_eq!(parse_partition_bounds(sql, "day"), (None, None));
+  }
+
+  #[test]
+  fn file_metadata_bounds_are_the_days_of_the_rows() {
+    let schema = json!({"day": {"type": "string"}, "v": {"type": "int"}});
+    let rows = vec![
+      json!({"day": "2024-01-03", "v": 1}),
+      json!({"day": "2024-01-01T10:00:00Z", "v": 2}),
+      json!({"v": 3}),
+    ];
+    let metadata: HashMap<String, Option<String>> = build_file_metadata("db", "t", &schema, &rows, Some("day"), "2024-01-05")
+      .into_iter()
+      .map(|key_value| (key_value.key, key_value.value))
+      .collect();
+    assert_eq!(metadata["timon.row_count"].as_deref(), Some("3"));
+    assert_eq!(metadata["timon.min_partition_date"].as_deref(), Some("2024-01-01"));
+    assert_eq!(metadata["timon.max_partition_date"].as_deref(), Some("2024-01-03"));
+    assert_eq!(
+      metadata["timon.schema_hash"].as_deref(),
+      Some(hex::encode(ring::digest::digest(&ring::digest::SHA256, schema.to_string().as_bytes())).as_str())
+    );
+
+    let metadata = build_file_metadata("db", "t", &schema, &rows, None, "2024-01-05");
+    let bounds: Vec<_> = metadata.iter().filter(|key_value| key_value.key.ends_with("_partition_date")).collect();
+    assert!(bounds.iter().all(|key_value| key_value.value.as_deref() == Some("2024-01-05")));
+  }
+
+  #[test]
+  fn dictionary_columns_written_outside_timon_convert_to_json() {
+    use arrow::array::DictionaryArray;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let file_path = dir.path().join("t_2024-01-01.parquet");
+    let names: DictionaryArray<Int32Type> = vec![Some("a"), Some("b"), None, Some("a")].into_iter().collect();
+    let batch = RecordBatch::try_from_iter([("name", Arc::new(names) as ArrayRef)]).unwrap();
+    let mut writer = ArrowWriter::try_new(fs::File::create(&file_path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&file_path).unwrap())
+      .unwrap()
+      .build()
+      .unwrap();
+    let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+    assert!(matches!(batches[0].schema().field(0).data_type(), DataType::Dictionary(_, _)));
+    let rows = record_batches_to_json(&batches, NonFiniteFloats::Null).unwrap();
+    assert_eq!(rows, json!([{"name": "a"}, {"name": "b"}, {"name": null}, {"name": "a"}]));
+  }
+
+  // A file laid out as Spark (parquet-mr) writes it: an INT96 timestamp and a flat group, written column by column
+  fn write_spark_style_fixture(file_path: &Path) {
+    use parquet::data_type::{ByteArray, ByteArrayType, Int32Type as ParquetInt32Type, Int96, Int96Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    let schema = "message spark_schema {
+      REQUIRED INT96 ts;
+      OPTIONAL group device {
+        OPTIONAL BYTE_ARRAY name (UTF8);
+        OPTIONAL INT32 level;
+      }
+    }";
+    let props = WriterProperties::builder().set_created_by("parquet-mr version 1.12.3".to_owned()).build();
+    let file = fs::File::create(file_path).unwrap();
+    let mut writer = SerializedFileWriter::new(file, Arc::new(parse_message_type(schema).unwrap()), Arc::new(props)).unwrap();
+    let mut row_group = writer.next_row_group().unwrap();
+    // 2024-01-01T00:00:01.500Z and 2024-01-02T00:00:00Z as nanoseconds of their Julian day
+    let timestamps: Vec<Int96> = [(1_500_000_000, 2_460_311), (0, 2_460_312)]
+      .into_iter()
+      .map(|(nanos, julian_day)| {
+        let mut timestamp = Int96::new();
+        timestamp.set_data(nanos, 0, julian_day);
+        timestamp
+      })
+      .collect();
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column.typed::<Int96Type>().write_batch(&timestamps, None, None).unwrap();
+    column.close().unwrap();
+    // The first row has a device, the second a null one
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column
+      .typed::<ByteArrayType>()
+      .write_batch(&[ByteArray::from("sensor-1")], Some(&[2, 0]), None)
+      .unwrap();
+    column.close().unwrap();
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column.typed::<ParquetInt32Type>().write_batch(&[3], Some(&[2, 0]), None).unwrap();
+    column.close().unwrap();
+    row_group.close().unwrap();
+    writer.close().unwrap();
+  }
+
+  #[tokio::test]
+  async fn int96_timestamps_and_groups_written_outside_timon_convert_to_json() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let file_path = dir.path().join("external.parquet");
+    write_spark_style_fixture(&file_path);
+    let file_path = file_path.to_str().unwrap();
+
+    let schema = read_parquet_schema(file_path).unwrap();
+    assert_eq!(schema.rows, 2);
+    let columns: Vec<(&str, &str)> = schema
+      .columns
+      .iter()
+      .map(|column| (column.name.as_str(), column.physical_type.as_str()))
+      .collect();
+    assert_eq!(columns, [("ts", "INT96"), ("device.name", "BYTE_ARRAY"), ("device.level", "INT32")]);
+    assert_eq!(schema.arrow_schema.field(0).data_type(), &DataType::Timestamp(TimeUnit::Nanosecond, None));
+    assert!(matches!(schema.arrow_schema.field(1).data_type(), DataType::Struct(_)));
+
+    let expected = json!([
+      {"ts": 1_704_067_201_500_i64, "device": {"name": "sensor-1", "level": 3}},
+      {"ts": 1_704_153_600_000_i64, "device": null}
+    ]);
+    let reader = SerializedFileReader::new(fs::File::open(file_path).unwrap()).unwrap();
+    let rows: Vec<Value> = reader.get_row_iter(None).unwrap().map(|row| row_to_json(&row.unwrap())).collect();
+    assert_eq!(Value::Array(rows), expected);
+
+    let ctx = SessionContext::new();
+    let df = query_parquet_file(&ctx, file_path, "SELECT ts, device FROM timon ORDER BY ts")
+      .await
+      .unwrap();
+    let rows = record_batches_to_json(&df.collect().await.unwrap(), NonFiniteFloats::Null).unwrap();
+    assert_eq!(rows, expected);
+  }
+
+  #[test]
+  fn non_finite_spellings_are_floats_only_in_float_fields() {
+    use arrow::datatypes::Float64Type;
+
+    let schema = json!({"label": {"type": "string"}, "reading": {"type": "float"}});
+    let rows = vec![json!({"label": "NaN", "reading": "NaN"}), json!({"label": "Infinity", "reading": 2.5})];
+    let (arrays, arrow_schema) = json_to_arrow_with_schema(&rows, &schema).unwrap();
+    let label = arrow_schema.index_of("label").unwrap();
+    let reading = arrow_schema.index_of("reading").unwrap();
+    assert_eq!(arrow_schema.field(label).data_type(), &DataType::Utf8);
+    assert_eq!(arrays[label].as_string::<i32>().value(0), "NaN");
+    assert_eq!(arrays[label].as_string::<i32>().value(1), "Infinity");
+    assert_eq!(arrow_schema.field(reading).data_type(), &DataType::Float64);
+    assert!(arrays[reading].as_primitive::<Float64Type>().value(0).is_nan());
+    assert_eq!(arrays[reading].as_primitive::<Float64Type>().value(1), 2.5);
+
+    let (_, arrow_schema) = json_to_arrow(&rows[..1]).unwrap();
+    assert_eq!(arrow_schema.field_with_name("reading").unwrap().data_type(), &DataType::Utf8);
+  }
+
+  #[test]
+  fn partition_sources_use_a_monthly_file_only_for_months_without_daily_files() {
+    let file = |day: &str, path: &str| (date(day).unwrap(), path.to_string());
+    let daily_files = vec![file("2024-01-31", "t_2024-01-31.parquet"), file("2024-02-01", "t_2024-02-01.parquet")];
+    let monthly_files = vec![file("2023-12-01", "t_2023-12.parquet"), file("2024-01-01", "t_2024-01.parquet")];
+
+    // Neither monthly file records its bounds: December has no daily file so its rollup is read, January's is not
+    let (daily, monthly) = select_partition_sources(daily_files.clone(), monthly_files.clone(), date("2023-12-15"), date("2024-02-01"));
+    assert_eq!(daily, daily_files);
+    assert_eq!(monthly, vec![file("2023-12-01", "t_2023-12.parquet")]);
+
+    // A range ending on the last day of December never reaches the daily files
+    let (daily, monthly) = select_partition_sources(daily_files, monthly_files, None, date("2023-12-31"));
+    assert!(daily.is_empty());
+    assert_eq!(monthly, vec![file("2023-12-01", "t_2023-12.parquet")]);
+  }
+
+  #[test]
+  fn timestamp_fields_accept_each_form_and_name_the_formats_they_tried() {
+    let schema = json!({"at": {"type": "timestamp"}, "label": {"type": "timestamp|string"}});
+    let millis = 1_724_014_712_000_i64; // 2024-08-18T20:58:32Z
+    let mut rows: Vec<Value> = [
+      json!(millis),
+      json!("2024-08-18T20:58:32Z"),
+      json!("2024-08-18T22:58:32+02:00"),
+      json!("2024-08-18T20:58:32"),
+      json!("2024-08-18 20:58:32"),
+      json!("2024.08.18 20:58:32"),
+    ]
+    .into_iter()
+    .map(|at| json!({"at": at}))
+    .collect();
+    normalize_declared_timestamps(&schema, &[], &mut rows).unwrap();
+    assert!(rows.iter().all(|row| row["at"] == json!(millis)), "{:?}", rows);
+
+    let mut fractional = vec![json!({"at": "2024-08-18 20:58:32.250"})];
+    normalize_declared_timestamps(&schema, &[], &mut fractional).unwrap();
+    assert_eq!(fractional[0]["at"], json!(millis + 250));
+
+    // A `timestamp|string` field keeps the strings that aren't timestamps
+    let mut labels = vec![json!({"label": "yesterday"})];
+    normalize_declared_timestamps(&schema, &[], &mut labels).unwrap();
+    assert_eq!(labels[0]["label"], json!("yesterday"));
+
+    let error = normalize_declared_timestamps(&schema, &[], &mut [json!({"at": "18/08/2024 20:58"})]).unwrap_err();
+    assert!(
+      error.contains("'18/08/2024 20:58'") && error.contains("'%Y.%m.%d %H:%M:%S%.f'"),
+      "{}",
+      error
+    );
+
+    // The table's `timestamp_formats` replace the defaults
+    let formats = vec!["%d/%m/%Y %H:%M".to_string()];
+    let mut custom = vec![json!({"at": "18/08/2024 20:58"})];
+    normalize_declared_timestamps(&schema, &formats, &mut custom).unwrap();
+    assert_eq!(custom[0]["at"], json!(millis - 32_000));
+    assert!(normalize_declared_timestamps(&schema, &formats, &mut [json!({"at": "2024-08-18 20:58:32"})]).is_err());
+  }
+
+  #[test]
+  fn json_to_arrow_converts_boolean_and_array_fields() {
+    let rows = vec![
+      json!({"online": true, "tags": ["a", "b"], "readings": [1.5, 2.5]}),
+      json!({"online": false, "tags": [], "readings": [3.0]}),
+    ];
+    let (arrays, schema) = json_to_arrow(&rows).unwrap();
+    let column = |name: &str| &arrays[schema.index_of(name).unwrap()];
+
+    assert_eq!(schema.field_with_name("online").unwrap().data_type(), &DataType::Boolean);
+    let online = column("online").as_boolean();
+    assert_eq!((online.value(0), online.value(1)), (true, false));
+
+    let tags = column("tags").as_list::<i32>();
+    assert_eq!(tags.value_type(), DataType::Utf8);
+    assert_eq!(tags.value(0).as_string::<i32>().iter().flatten().collect::<Vec<_>>(), ["a", "b"]);
+    assert!(tags.value(1).is_empty());
+
+    let readings = column("readings").as_list::<i32>();
+    assert_eq!(readings.value_type(), DataType::Float64);
+    assert_eq!(readings.value(1).as_primitive::<arrow::datatypes::Float64Type>().values().to_vec(), [3.0]);
+  }
+
+  #[test]
+  fn relative_ranges_cover_the_partition_days_of_the_callers_days() {
+    let now = DateTime::parse_from_rfc3339("2024-03-10T20:00:00Z").unwrap().with_timezone(&Utc);
+    let resolve = |pairs: &[(&str, &str)]| {
+      let date_range: HashMap<String, String> = pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+      resolve_relative_date_range_at(&date_range, now).map(|resolved| (resolved["start_date"].clone(), resolved["end_date"].clone()))
+    };
+    let range = |start: &str, end: &str| Ok((start.to_string(), end.to_string()));
+
+    assert_eq!(resolve(&[("last", "1d")]), range("2024-03-10", "2024-03-10"));
+    assert_eq!(resolve(&[("last", "1w")]), range("2024-03-04", "2024-03-10"));
+    // Already March 11 at +05:30, whose day started at 18:30 UTC on March 10
+    assert_eq!(resolve(&[("last", "1d"), ("utc_offset", "+05:30")]), range("2024-03-10", "2024-03-11"));
+    // Still March 10 at -08:00, whose days end at 08:00 UTC the next day
+    assert_eq!(
+      resolve(&[("since", "2024-03-01"), ("utc_offset", "-08:00")]),
+      range("2024-03-01", "2024-03-11")
+    );
+    assert_eq!(resolve(&[("last", "7d"), ("end_date", "2024-03-08")]), range("2024-03-04", "2024-03-08"));
+    assert!(resolve(&[("last", "1d"), ("utc_offset", "Europe/Paris")]).is_err());
+  }
+
+  #[test]
+  fn relative_ranges_reject_a_zero_or_overflowing_amount() {
+    let resolve = |last: &str| resolve_relative_date_range_at(&HashMap::from([("last".to_string(), last.to_string())]), Utc::now());
+    assert_eq!(resolve("0d").unwrap_err(), "Invalid relative range '0d', expected e.g. 7d, 2w, 1mo or 1y");
+    assert_eq!(resolve("0y").unwrap_err(), "Invalid relative range '0y', expected e.g. 7d, 2w, 1mo or 1y");
+    assert_eq!(resolve("400000000y").unwrap_err(), "Relative range '400000000y' is out of bounds");
+    assert_eq!(
+      resolve("5000000000d").unwrap_err(),
+      "Invalid relative range '5000000000d', expected e.g. 7d, 2w, 1mo or 1y"
+    );
+  }
+}
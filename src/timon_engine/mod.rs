@@ -1,45 +1,279 @@
 pub mod cloud_sync;
 pub mod db_manager;
+pub mod encryption;
 pub mod helpers;
+pub mod stats;
+pub mod udfs;
 
-use cloud_sync::CloudStorageManager;
-use db_manager::DatabaseManager;
+use base64::{engine::general_purpose, Engine as _};
+use cloud_sync::{BucketClientConfig, CloudStorageManager, ProgressCallback};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use db_manager::{
+  AttachOptions, DatabaseManager, DatabaseOptions, InsertMode, InvalidRows, MetadataIssue, NoDataInRange, QueryConfig, QueryIntoOptions,
+  QueryOptions, QuotaExceeded, ReadOptions, StorageConfig, TableNotFound, TableOptions,
+};
+use encryption::FieldCipher;
+use helpers::{record_batches_to_output, CompressionCodec, NonFiniteFloats, OutputFormat, SchemaSource};
+use serde::ser::SerializeStruct;
 use serde::Serialize;
-use serde_json::{self, Value};
+use serde_json::{self, json, Value};
 use std::collections::HashMap;
-use std::sync::OnceLock;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
 
 /* ******************************** File Storage ********************************
-* @ init_timon/new(storage_path)
-* @ create_database(db_name)
-* @ create_table(db_name, table_name)
-* @ list_databases() & list_tables(db_name)
-* @ delete_database(db_name) & delete_table(db_name, table_name)
-* @ insert(db_name, table_name, json_data)
-* @ query(db_name, date_range, sql_query)
+* @ init_timon/new(storage_path) & init_timon_with_config(storage_path, config) & init_timon_with_query_config(storage_path, query_config_json)
+* @ init_timon_with_udfs(storage_path, config, query_config, udfs) & init_timon_read_only(storage_path)
+* @ init_timon_with_encryption_key(storage_path, encryption_key)
+* @ metadata_status() & compact_metadata() & set_compression(codec)
+* @ create_database(db_name) & create_databases(db_names) & create_database_with_options(db_name, options_json)
+* @ create_table(db_name, table_name) & create_tables(db_name, tables_json)
+* @ create_table_with_options(db_name, table_name, schema, options_json)
+* @ create_table_inferred(db_name, table_name, sample_json)
+* @ list_databases() & list_tables(db_name) & list_tables_detailed(db_name) & list_table_files(db_name, table_name, date_range)
+* @ available_dates(db_name, table_name) & database_size(db_name)
+* @ database_exists(db_name) & table_exists(db_name, table_name) & table_version(db_name, table_name)
+* @ delete_database(db_name) & delete_table(db_name, table_name) & truncate_table(db_name, table_name)
+* @ merge_tables(db_name, source_table, dest_table)
+* @ attach_table(db_name, table_name, source_dir, schema, options_json) & detach_table(db_name, table_name)
+* @ insert(db_name, table_name, json_data) & insert_with_mode(db_name, table_name, json_data, insert_mode_json)
+* @ upsert(db_name, table_name, json_data)
+* @ insert_ndjson(db_name, table_name, ndjson_data) & insert_ndjson_file(db_name, table_name, file_path)
+* @ insert_compressed(db_name, table_name, data, codec)
+* @ checkpoint() & recover() & version() & get_stats()
+* @ create_database_async, create_databases_async, create_table_async, create_tables_async & insert_async
+* @ query(db_name, date_range, sql_query) & query_with_options(db_name, sql_query, date_range, options)
+* @ query_with_format(db_name, sql_query, date_range, format) & query_with_options_json(db_name, sql_query, date_range, options_json)
+* @ query_at(storage_path, db_name, sql_query, date_range) & insert_at(storage_path, db_name, table_name, json_data)
+* @ explain(db_name, sql_query, date_range, analyze)
+* @ query_into(db_name, sql_query, date_range, dest_db, dest_table, options_json)
+* @ create_table_as(db_name, table_name, date_range, select_sql)
+* @ query_day(db_name, table_name, date, sql_query, format) & query_file(file_path, sql_query, format)
+* @ sample(db_name, table_name, date_range, n)
+* @ count_rows(db_name, table_name, date_range)
  */
-#[derive(Serialize)]
+// Version of the `{version, status, message, json_value}` envelope every function returns, raised whenever the
+// envelope or a `json_value` payload changes shape (see the README's "Result Envelope" section)
+pub const RESULT_ENVELOPE_VERSION: u16 = 2;
+
 pub struct TimonResult {
   pub status: u16,
   pub message: String,
   pub json_value: Option<Value>,
 }
 
+impl Serialize for TimonResult {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("TimonResult", 4)?;
+    state.serialize_field("version", &RESULT_ENVELOPE_VERSION)?;
+    state.serialize_field("status", &self.status)?;
+    state.serialize_field("message", &self.message)?;
+    state.serialize_field("json_value", &self.json_value)?;
+    state.end()
+  }
+}
+
+// Build information for bug reports: the crate version, the git commit when the build sets `TIMON_GIT_HASH`, and the
+// enabled cargo features (S3 support is always compiled in)
+#[allow(dead_code)]
+pub fn version() -> Result<Value, String> {
+  let mut features = Vec::new();
+  if cfg!(feature = "dev_cli") {
+    features.push("dev_cli");
+  }
+  let result = TimonResult {
+    status: 200,
+    message: format!("Timon {}", env!("CARGO_PKG_VERSION")),
+    json_value: Some(json!({
+      "version": env!("CARGO_PKG_VERSION"),
+      "git_hash": option_env!("TIMON_GIT_HASH"),
+      "features": features,
+      "s3": true,
+    })),
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// Snapshot of the process's insert, query and bucket transfer counters, see `stats::StatsSnapshot`
+#[allow(dead_code)]
+pub fn get_stats() -> Result<Value, String> {
+  let result = TimonResult {
+    status: 200,
+    message: "Stats since the process started".to_owned(),
+    json_value: Some(serde_json::to_value(stats::snapshot()).map_err(|e| e.to_string())?),
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// The string every Android and iOS export returns: the envelope of the engine's answer, or a 400 envelope with the
+// failure prefixed by `context` when the call couldn't produce one (e.g. before `init_timon`)
+#[allow(dead_code)]
+pub fn ffi_response(result: Result<Value, String>, context: &str) -> String {
+  match result {
+    Ok(value) => value.to_string(),
+    Err(err) => ffi_error(&format!("{}: {}", context, err)),
+  }
+}
+
+// A 400 envelope for failures before the engine is called (e.g. invalid FFI arguments)
+#[allow(dead_code)]
+pub fn ffi_error(message: &str) -> String {
+  let result = TimonResult {
+    status: 400,
+    message: message.to_owned(),
+    json_value: None,
+  };
+  // Through a Value like the engine's answers, so both come out with the same key order
+  serde_json::to_value(&result)
+    .map(|value| value.to_string())
+    .unwrap_or_else(|_| "{}".to_string())
+}
+
+#[derive(Debug)]
+pub enum TimonError {
+  NotInitialized { manager: &'static str, init_function: &'static str }, // A function ran before the init call it depends on
+  MetadataUnreadable(String), // metadata.json and its backup couldn't be read at startup and still can't be
+}
+
+impl fmt::Display for TimonError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TimonError::NotInitialized { manager, init_function } => write!(f, "{} is not initialized, call '{}' first", manager, init_function),
+      TimonError::MetadataUnreadable(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl std::error::Error for TimonError {}
+
+// Lets the public functions, which report errors as strings, propagate a `TimonError` with `?`
+impl From<TimonError> for String {
+  fn from(error: TimonError) -> Self {
+    error.to_string()
+  }
+}
+
 static DATABASE_MANAGER: OnceLock<DatabaseManager> = OnceLock::new();
 
-fn get_database_manager() -> &'static DatabaseManager {
-  DATABASE_MANAGER.get().expect("DatabaseManager is not initialized")
+// Refuses to run against unreadable metadata, which would otherwise read as a store without any database
+fn get_database_manager() -> Result<&'static DatabaseManager, TimonError> {
+  let database_manager = DATABASE_MANAGER.get().ok_or(TimonError::NotInitialized {
+    manager: "DatabaseManager",
+    init_function: "init_timon",
+  })?;
+  database_manager.check_metadata().map_err(TimonError::MetadataUnreadable)?;
+  Ok(database_manager)
+}
+
+// Managers of the stores addressed by path (`query_at`, `insert_at`), created on first use with the query config
+// of the global manager when there is one
+static STORE_MANAGERS: OnceLock<RwLock<HashMap<String, DatabaseManager>>> = OnceLock::new();
+
+fn get_store_manager(storage_path: &str) -> DatabaseManager {
+  let store_managers = STORE_MANAGERS.get_or_init(|| RwLock::new(HashMap::new()));
+  if let Some(database_manager) = store_managers.read().unwrap().get(storage_path) {
+    return database_manager.clone();
+  }
+  let mut store_managers = store_managers.write().unwrap();
+  store_managers
+    .entry(storage_path.to_owned())
+    .or_insert_with(|| {
+      let mut database_manager = DatabaseManager::new(storage_path);
+      if let Ok(global_manager) = get_database_manager() {
+        database_manager.query_config = global_manager.query_config.clone();
+      }
+      database_manager
+    })
+    .clone()
 }
 
 #[allow(dead_code)]
 pub fn init_timon(storage_path: &str) -> Result<Value, String> {
-  let db_manager = DatabaseManager::new(storage_path);
+  init_timon_with_config(storage_path, StorageConfig::default(), QueryConfig::default())
+}
+
+// Same as `init_timon` with the query tuning (result limits, partitions, batch size, memory limit, file count) given as a `QueryConfig` JSON
+#[allow(dead_code)]
+pub fn init_timon_with_query_config(storage_path: &str, query_config_json: &str) -> Result<Value, String> {
+  match serde_json::from_str::<QueryConfig>(query_config_json) {
+    Ok(query_config) => init_timon_with_config(storage_path, StorageConfig::default(), query_config),
+    Err(e) => {
+      let result = TimonResult {
+        status: 400,
+        message: format!("Invalid query config: {}", e),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+// Open an existing store for inspection only, e.g. from a debugging tool or a second process next to the writer: nothing
+// is created on disk and every write fails with a `ReadOnly` error
+#[allow(dead_code)]
+pub fn init_timon_read_only(storage_path: &str) -> Result<Value, String> {
+  let config = StorageConfig {
+    read_only: true,
+    ..StorageConfig::default()
+  };
+  init_timon_with_config(storage_path, config, QueryConfig::default())
+}
+
+// Same as `init_timon` with the base64 encoded 32-byte key the fields declared `encrypted` are encrypted on insert and
+// decrypted by `query` with. Opened without it, a store returns these fields as stored and refuses inserts into them.
+#[allow(dead_code)]
+pub fn init_timon_with_encryption_key(storage_path: &str, encryption_key: &str) -> Result<Value, String> {
+  let config = StorageConfig {
+    encryption_key: Some(encryption_key.to_owned()),
+    ..StorageConfig::default()
+  };
+  init_timon_with_config(storage_path, config, QueryConfig::default())
+}
+
+#[allow(dead_code)]
+pub fn init_timon_with_config(storage_path: &str, config: StorageConfig, query_config: QueryConfig) -> Result<Value, String> {
+  init_timon_with_udfs(storage_path, config, query_config, Vec::new())
+}
+
+// Same as `init_timon_with_config` for Rust embedders, with scalar UDFs every query can call besides the built-in ones
+// (see `udfs::builtin_udfs`); a UDF named like a built-in one replaces it
+#[allow(dead_code)]
+pub fn init_timon_with_udfs(
+  storage_path: &str,
+  config: StorageConfig,
+  query_config: QueryConfig,
+  udfs: Vec<datafusion::logical_expr::ScalarUDF>,
+) -> Result<Value, String> {
+  // A wrong key would only show once data is inserted or read, so the store isn't opened with one
+  if let Some(Err(e)) = config.encryption_key.as_deref().map(FieldCipher::from_base64) {
+    let result = TimonResult {
+      status: 400,
+      message: e,
+      json_value: None,
+    };
+    return serde_json::to_value(&result).map_err(|e| e.to_string());
+  }
+  let mut db_manager = DatabaseManager::new_with_config(storage_path, config);
+  db_manager.query_config = query_config;
+  db_manager.udfs = udfs;
+  let metadata_issue = db_manager.metadata_issue().cloned();
+  let read_only = db_manager.is_read_only();
   match DATABASE_MANAGER.set(db_manager) {
     Ok(_) => {
+      // The manager is kept either way, so a store whose metadata gets restored can be used without another init
+      let (status, message) = match &metadata_issue {
+        None if read_only => (200, "DatabaseManager initialized read-only".to_owned()),
+        None => (200, "DatabaseManager initialized successfully".to_owned()),
+        Some(MetadataIssue::Recovered { error, .. }) => (200, format!("DatabaseManager initialized, metadata restored from its backup: {}", error)),
+        Some(MetadataIssue::Unreadable { error, .. }) => (400, format!("DatabaseManager initialized with unreadable metadata: {}", error)),
+      };
       let result = TimonResult {
-        status: 200,
-        message: "DatabaseManager initialized successfully".to_owned(),
-        json_value: None,
+        status,
+        message,
+        json_value: metadata_issue
+          .map(|issue| serde_json::to_value(issue).map_err(|e| e.to_string()))
+          .transpose()?,
       };
       serde_json::to_value(&result).map_err(|e| e.to_string())
     }
@@ -54,9 +288,77 @@ pub fn init_timon(storage_path: &str) -> Result<Value, String> {
   }
 }
 
+// What was wrong with the metadata at startup (`null` when nothing was), even while it's unreadable
+#[allow(dead_code)]
+pub fn metadata_status() -> Result<Value, String> {
+  let database_manager = DATABASE_MANAGER.get().ok_or(TimonError::NotInitialized {
+    manager: "DatabaseManager",
+    init_function: "init_timon",
+  })?;
+  // Unreadable metadata the app has since restored no longer counts
+  let issue = match database_manager.metadata_issue() {
+    Some(MetadataIssue::Unreadable { .. }) if database_manager.check_metadata().is_ok() => None,
+    issue => issue,
+  };
+  let result = TimonResult {
+    status: 200,
+    message: match issue {
+      None => "Metadata is readable".to_owned(),
+      Some(MetadataIssue::Recovered { .. }) => "Metadata was restored from its backup at startup".to_owned(),
+      Some(MetadataIssue::Unreadable { .. }) => "Metadata is unreadable".to_owned(),
+    },
+    json_value: Some(serde_json::to_value(issue).map_err(|e| e.to_string())?),
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// Make table paths relative to the data directory (e.g. after restoring the store on a new device) and drop the
+// databases and tables whose directory is gone
+// Compress the Parquet files written from now on with `codec` ("snappy", "zstd", "gzip", "lz4", "brotli" or
+// "uncompressed", optionally with a level like "zstd(9)"), kept in the metadata across restarts
+#[allow(dead_code)]
+pub fn set_compression(codec: &str) -> Result<Value, String> {
+  let result = match get_database_manager()?.clone().set_compression(codec) {
+    Ok(codec) => TimonResult {
+      status: 200,
+      message: format!("Parquet files are now written with '{}' compression", codec),
+      json_value: None,
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn compact_metadata() -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let result = match database_manager.clone().compact_metadata() {
+    Ok(compaction) => TimonResult {
+      status: 200,
+      message: format!(
+        "Metadata compacted: {} path(s) rewritten, {} table(s) and {} database(s) dropped",
+        compaction.rewritten_paths,
+        compaction.dropped_tables.len(),
+        compaction.dropped_databases.len()
+      ),
+      json_value: Some(serde_json::to_value(&compaction).map_err(|e| e.to_string())?),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
 #[allow(dead_code)]
 pub fn create_database(db_name: &str) -> Result<Value, String> {
-  let database_manager = get_database_manager();
+  let database_manager = get_database_manager()?;
   match database_manager.clone().create_database(db_name) {
     Ok(_) => {
       let result = TimonResult {
@@ -77,9 +379,59 @@ pub fn create_database(db_name: &str) -> Result<Value, String> {
   }
 }
 
+// Same as `create_database` with `DatabaseOptions` JSON, e.g. `{"quota_bytes": 52428800}` to cap the database at 50 MiB
+#[allow(dead_code)]
+pub fn create_database_with_options(db_name: &str, options_json: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let created = serde_json::from_str::<DatabaseOptions>(options_json)
+    .map_err(|e| format!("Invalid database options: {}", e))
+    .and_then(|options| {
+      database_manager
+        .clone()
+        .create_database_with_options(db_name, options)
+        .map_err(|e| e.to_string())
+    });
+  let result = match created {
+    Ok(_) => TimonResult {
+      status: 200,
+      message: format!("'{}' database created successfully", db_name),
+      json_value: None,
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err,
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn create_databases(db_names: &[&str]) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  match database_manager.clone().create_databases(db_names) {
+    Ok(_) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("{:?} databases created successfully", db_names),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
 #[allow(dead_code)]
 pub fn create_table(db_name: &str, table_name: &str, schema: &str) -> Result<Value, String> {
-  let database_manager = get_database_manager();
+  let database_manager = get_database_manager()?;
   match database_manager.clone().create_table(db_name, table_name, schema) {
     Ok(_) => {
       let result = TimonResult {
@@ -100,9 +452,88 @@ pub fn create_table(db_name: &str, table_name: &str, schema: &str) -> Result<Val
   }
 }
 
+// `tables_json` maps each table name to its schema, e.g. `{"temperature": {"date": {"type": "string"}}}`
+#[allow(dead_code)]
+pub fn create_tables(db_name: &str, tables_json: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let created = serde_json::from_str::<serde_json::Map<String, Value>>(tables_json)
+    .map_err(|e| format!("Invalid tables definition: {}", e).into())
+    .and_then(|tables| {
+      let tables = tables.into_iter().map(|(table_name, schema)| (table_name, schema.to_string())).collect();
+      database_manager.clone().create_tables(db_name, tables)
+    });
+  match created {
+    Ok(table_names) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("{} tables created successfully in '{}'", table_names.len(), db_name),
+        json_value: Some(serde_json::to_value(table_names).map_err(|e| e.to_string())?),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+#[allow(dead_code)]
+pub fn create_table_with_options(db_name: &str, table_name: &str, schema: &str, options_json: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let created = serde_json::from_str::<TableOptions>(options_json)
+    .map_err(|e| format!("Invalid table options: {}", e).into())
+    .and_then(|options| database_manager.clone().create_table_with_options(db_name, table_name, schema, options));
+  match created {
+    Ok(_) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("'{}.{}' table created successfully", db_name, table_name),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+#[allow(dead_code)]
+pub fn create_table_inferred(db_name: &str, table_name: &str, sample_json: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  match database_manager.clone().create_table_inferred(db_name, table_name, sample_json) {
+    Ok(_) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("'{}.{}' table created successfully with an inferred schema", db_name, table_name),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
 #[allow(dead_code)]
 pub fn list_databases() -> Result<Value, String> {
-  let mut database_manager = get_database_manager().clone();
+  let mut database_manager = get_database_manager()?.clone();
   match database_manager.list_databases() {
     Ok(databases_list) => {
       let json_value = serde_json::to_value(databases_list).map_err(|e| e.to_string())?;
@@ -126,7 +557,7 @@ pub fn list_databases() -> Result<Value, String> {
 
 #[allow(dead_code)]
 pub fn list_tables(db_name: &str) -> Result<Value, String> {
-  let mut database_manager = get_database_manager().clone();
+  let mut database_manager = get_database_manager()?.clone();
   match database_manager.list_tables(db_name) {
     Ok(tables_list) => {
       let json_value = serde_json::to_value(&tables_list).map_err(|e| e.to_string())?;
@@ -148,9 +579,170 @@ pub fn list_tables(db_name: &str) -> Result<Value, String> {
   }
 }
 
+#[allow(dead_code)]
+pub fn list_tables_detailed(db_name: &str) -> Result<Value, String> {
+  let mut database_manager = get_database_manager()?.clone();
+  match database_manager.list_tables_detailed(db_name) {
+    Ok(tables_details) => {
+      let json_value = serde_json::to_value(&tables_details).map_err(|e| e.to_string())?;
+      let result = TimonResult {
+        status: 200,
+        message: format!("success fetching '{}' tables details", db_name),
+        json_value: Some(json_value),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+#[allow(dead_code)]
+pub fn database_size(db_name: &str) -> Result<Value, String> {
+  let mut database_manager = get_database_manager()?.clone();
+  let result = match database_manager.database_size(db_name) {
+    Ok(usage) => TimonResult {
+      status: 200,
+      message: format!("'{}' uses {} bytes", db_name, usage.size_bytes),
+      json_value: Some(serde_json::to_value(&usage).map_err(|e| e.to_string())?),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn list_table_files(db_name: &str, table_name: &str, date_range: Option<HashMap<String, String>>) -> Result<Value, String> {
+  match get_database_manager()?.list_table_files(db_name, table_name, date_range) {
+    Ok(files) => {
+      let json_value = serde_json::to_value(&files).map_err(|e| e.to_string())?;
+      let result = TimonResult {
+        status: 200,
+        message: format!("success listing the files of '{}.{}'", db_name, table_name),
+        json_value: Some(json_value),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => query_error_result(err),
+  }
+}
+
+// The days (`YYYY-MM-DD`) and months read from a monthly file (`YYYY-MM`) that a table has files for, sorted
+#[allow(dead_code)]
+pub fn available_dates(db_name: &str, table_name: &str) -> Result<Value, String> {
+  match get_database_manager()?.available_dates(db_name, table_name) {
+    Ok(dates) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("'{}.{}' has data on {} dates", db_name, table_name, dates.len()),
+        json_value: Some(json!(dates)),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => query_error_result(err),
+  }
+}
+
+#[allow(dead_code)]
+pub fn database_exists(db_name: &str) -> Result<Value, String> {
+  let exists = get_database_manager()?.database_exists(db_name);
+  let result = TimonResult {
+    status: 200,
+    message: format!("database '{}' {}", db_name, if exists { "exists" } else { "does not exist" }),
+    json_value: Some(Value::Bool(exists)),
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn table_exists(db_name: &str, table_name: &str) -> Result<Value, String> {
+  let exists = get_database_manager()?.table_exists(db_name, table_name);
+  let result = TimonResult {
+    status: 200,
+    message: format!("table '{}.{}' {}", db_name, table_name, if exists { "exists" } else { "does not exist" }),
+    json_value: Some(Value::Bool(exists)),
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn table_version(db_name: &str, table_name: &str) -> Result<Value, String> {
+  match get_database_manager()?.table_version(db_name, table_name) {
+    Ok(version) => {
+      let json_value = serde_json::to_value(&version).map_err(|e| e.to_string())?;
+      let result = TimonResult {
+        status: 200,
+        message: format!("'{}.{}' is at version {}", db_name, table_name, version.version),
+        json_value: Some(json_value),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+// fsync every file written since the last checkpoint, for callers that need their inserts to survive a power loss
+// without paying for `sync_writes` on each of them
+#[allow(dead_code)]
+pub fn checkpoint() -> Result<Value, String> {
+  match get_database_manager()?.checkpoint() {
+    Ok(synced) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("{} paths synced to disk", synced),
+        json_value: Some(Value::from(synced)),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+// Replay the logged inserts of `wal` tables that a crash interrupted; `init_timon` already does it at startup
+#[allow(dead_code)]
+pub fn recover() -> Result<Value, String> {
+  let result = match get_database_manager()?.clone().recover() {
+    Ok(replayed) => TimonResult {
+      status: 200,
+      message: format!("{} logged insert(s) replayed", replayed),
+      json_value: Some(Value::from(replayed)),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
 #[allow(dead_code)]
 pub fn delete_database(db_name: &str) -> Result<Value, String> {
-  let database_manager = get_database_manager();
+  let database_manager = get_database_manager()?;
   match database_manager.clone().delete_database(db_name) {
     Ok(_) => {
       let result = TimonResult {
@@ -173,7 +765,7 @@ pub fn delete_database(db_name: &str) -> Result<Value, String> {
 
 #[allow(dead_code)]
 pub fn delete_table(db_name: &str, table_name: &str) -> Result<Value, String> {
-  let database_manager = get_database_manager();
+  let database_manager = get_database_manager()?;
   match database_manager.clone().delete_table(db_name, table_name) {
     Ok(_) => {
       let result = TimonResult {
@@ -195,13 +787,13 @@ pub fn delete_table(db_name: &str, table_name: &str) -> Result<Value, String> {
 }
 
 #[allow(dead_code)]
-pub fn insert(db_name: &str, table_name: &str, json_data: &str) -> Result<Value, String> {
-  let database_manager = get_database_manager();
-  match database_manager.clone().insert(db_name, table_name, json_data) {
-    Ok(message) => {
+pub fn truncate_table(db_name: &str, table_name: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  match database_manager.clone().truncate_table(db_name, table_name) {
+    Ok(_) => {
       let result = TimonResult {
         status: 200,
-        message,
+        message: format!("Table '{}.{}' was truncated!", db_name, table_name),
         json_value: None,
       };
       serde_json::to_value(&result).map_err(|e| e.to_string())
@@ -217,24 +809,336 @@ pub fn insert(db_name: &str, table_name: &str, json_data: &str) -> Result<Value,
   }
 }
 
+#[allow(dead_code)]
+pub fn merge_tables(db_name: &str, source_table: &str, dest_table: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  match database_manager.clone().merge_tables(db_name, source_table, dest_table) {
+    Ok(summary) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("Table '{}.{}' was merged into '{}'!", db_name, source_table, dest_table),
+        json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: if err.downcast_ref::<TableNotFound>().is_some() { 404 } else { 400 },
+        message: err.to_string(),
+        json_value: None,
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+  }
+}
+
+// Register a directory of `{table}_{date}.parquet` files as a new table, with `schema` or one inferred from the files
+// when it's empty; `options_json` is an `AttachOptions` JSON, e.g. `{"link": true}`, or empty
+#[allow(dead_code)]
+pub fn attach_table(db_name: &str, table_name: &str, source_dir: &str, schema: &str, options_json: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let options = match options_json.trim() {
+    "" => Ok(AttachOptions::default()),
+    options_json => serde_json::from_str::<AttachOptions>(options_json).map_err(|e| format!("Invalid attach options: {}", e)),
+  };
+  let attached = options
+    .map_err(|e| e.into())
+    .and_then(|options| database_manager.clone().attach_table(db_name, table_name, source_dir, schema, options));
+  let result = match attached {
+    Ok(summary) => TimonResult {
+      status: 200,
+      message: format!("{} files were attached as table '{}.{}'", summary.files, db_name, table_name),
+      json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn detach_table(db_name: &str, table_name: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let result = match database_manager.clone().detach_table(db_name, table_name) {
+    Ok(table_dir) => TimonResult {
+      status: 200,
+      message: format!("Table '{}.{}' was detached, its files are kept in '{}'", db_name, table_name, table_dir),
+      json_value: Some(Value::String(table_dir)),
+    },
+    Err(err) => TimonResult {
+      status: if err.downcast_ref::<TableNotFound>().is_some() { 404 } else { 400 },
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub fn insert(db_name: &str, table_name: &str, json_data: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  insert_result(database_manager.clone().insert(db_name, table_name, json_data))
+}
+
+// Same as `insert` with an explicit `InsertMode` JSON, e.g. `{"mode": "upsert", "keys": ["id"]}`; an empty mode keeps the
+// default deduplication on the schema's `unique` fields
+#[allow(dead_code)]
+pub fn insert_with_mode(db_name: &str, table_name: &str, json_data: &str, insert_mode_json: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let inserted = parse_insert_mode(insert_mode_json)
+    .map_err(|e| e.into())
+    .and_then(|(mode, max_errors, schema_source)| {
+      database_manager
+        .clone()
+        .insert_with_mode(db_name, table_name, json_data, mode, max_errors, schema_source)
+    });
+  insert_result(inserted)
+}
+
+// Insert or replace rows by the table's `unique` fields, reading only the key columns of the stored files
+#[allow(dead_code)]
+pub fn upsert(db_name: &str, table_name: &str, json_data: &str) -> Result<Value, String> {
+  insert_result(get_database_manager()?.clone().upsert(db_name, table_name, json_data))
+}
+
+// Split `insertWithMode`'s JSON into the insert mode (its `mode` tag and keys, none when absent), `max_errors` and
+// `schema_source`
+type InsertModeParts = (Option<InsertMode>, Option<usize>, Option<SchemaSource>);
+
+fn parse_insert_mode(insert_mode_json: &str) -> Result<InsertModeParts, String> {
+  if insert_mode_json.trim().is_empty() {
+    return Ok((None, None, None));
+  }
+  let mut fields: serde_json::Map<String, Value> = serde_json::from_str(insert_mode_json).map_err(|e| format!("Invalid insert mode: {}", e))?;
+  let max_errors = match fields.remove("max_errors") {
+    None | Some(Value::Null) => None,
+    Some(value) => Some(value.as_u64().ok_or("Invalid insert mode: 'max_errors' must be a non-negative integer")? as usize),
+  };
+  let schema_source = match fields.remove("schema_source") {
+    None | Some(Value::Null) => None,
+    Some(value) => Some(serde_json::from_value::<SchemaSource>(value).map_err(|e| format!("Invalid insert mode: 'schema_source' {}", e))?),
+  };
+  if fields.is_empty() {
+    return Ok((None, max_errors, schema_source));
+  }
+  let mode = serde_json::from_value::<InsertMode>(Value::Object(fields)).map_err(|e| format!("Invalid insert mode: {}", e))?;
+  Ok((Some(mode), max_errors, schema_source))
+}
+
+// Rows per chunk `insert_ndjson` and `insert_ndjson_file` validate and write at a time
+const NDJSON_CHUNK_ROWS: usize = 10_000;
+
+// Same as `insert` with newline-delimited JSON, one object per line, written in chunks instead of as one batch
+#[allow(dead_code)]
+pub fn insert_ndjson(db_name: &str, table_name: &str, ndjson_data: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  insert_result(
+    database_manager
+      .clone()
+      .insert_ndjson(db_name, table_name, ndjson_data.as_bytes(), NDJSON_CHUNK_ROWS),
+  )
+}
+
+// Same as `insert_ndjson`, streaming the lines from a file (e.g. a device dump) so it never sits in memory whole
+#[allow(dead_code)]
+pub fn insert_ndjson_file(db_name: &str, table_name: &str, file_path: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let inserted = std::fs::File::open(file_path)
+    .map_err(|e| format!("Failed to open '{}': {}", file_path, e).into())
+    .and_then(|file| {
+      database_manager
+        .clone()
+        .insert_ndjson(db_name, table_name, std::io::BufReader::new(file), NDJSON_CHUNK_ROWS)
+    });
+  insert_result(inserted)
+}
+
+// Same as `insert` (JSON array) or `insert_ndjson` (anything else) with a gzip or zstd compressed payload, e.g. a
+// device upload handed through as received. `codec` is "gzip" or "zstd"
+#[allow(dead_code)]
+pub fn insert_compressed(db_name: &str, table_name: &str, data: &[u8], codec: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let inserted = CompressionCodec::parse(codec).map_err(|e| e.into()).and_then(|codec| {
+    database_manager
+      .clone()
+      .insert_compressed(db_name, table_name, data, codec, NDJSON_CHUNK_ROWS)
+  });
+  insert_result(inserted)
+}
+
+fn insert_result(inserted: Result<(String, db_manager::InsertSummary), Box<dyn std::error::Error>>) -> Result<Value, String> {
+  let result = match inserted {
+    Ok((message, summary)) => TimonResult {
+      status: 200,
+      message,
+      json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
+    },
+    Err(err) => {
+      // A batch validated as a whole reports every failing row it kept, and an insert over its database's quota the
+      // sizes it was refused on
+      let (status, details) = if let Some(invalid_rows) = err.downcast_ref::<InvalidRows>() {
+        (400, Some(serde_json::to_value(invalid_rows)))
+      } else if let Some(quota_exceeded) = err.downcast_ref::<QuotaExceeded>() {
+        (507, Some(serde_json::to_value(quota_exceeded)))
+      } else {
+        (400, None)
+      };
+      TimonResult {
+        status,
+        message: err.to_string(),
+        json_value: details.transpose().map_err(|e| e.to_string())?,
+      }
+    }
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// Same as `insert` on the store at `storage_path` instead of the one given to `init_timon`
+#[allow(dead_code)]
+pub fn insert_at(storage_path: &str, db_name: &str, table_name: &str, json_data: &str) -> Result<Value, String> {
+  insert_result(get_store_manager(storage_path).insert(db_name, table_name, json_data))
+}
+
+// Async variants of the blocking engine calls: the file I/O runs on tokio's blocking thread pool so callers on an
+// async runtime don't stall their executor
+async fn run_blocking<F>(f: F) -> Result<Value, String>
+where
+  F: FnOnce() -> Result<Value, String> + Send + 'static,
+{
+  tokio::task::spawn_blocking(f).await.map_err(|e| e.to_string())?
+}
+
+#[allow(dead_code)]
+pub async fn create_database_async(db_name: &str) -> Result<Value, String> {
+  let db_name = db_name.to_owned();
+  run_blocking(move || create_database(&db_name)).await
+}
+
+#[allow(dead_code)]
+pub async fn create_databases_async(db_names: &[&str]) -> Result<Value, String> {
+  let db_names: Vec<String> = db_names.iter().map(|db_name| db_name.to_string()).collect();
+  run_blocking(move || create_databases(&db_names.iter().map(String::as_str).collect::<Vec<_>>())).await
+}
+
+#[allow(dead_code)]
+pub async fn create_table_async(db_name: &str, table_name: &str, schema: &str) -> Result<Value, String> {
+  let (db_name, table_name, schema) = (db_name.to_owned(), table_name.to_owned(), schema.to_owned());
+  run_blocking(move || create_table(&db_name, &table_name, &schema)).await
+}
+
+#[allow(dead_code)]
+pub async fn create_tables_async(db_name: &str, tables_json: &str) -> Result<Value, String> {
+  let (db_name, tables_json) = (db_name.to_owned(), tables_json.to_owned());
+  run_blocking(move || create_tables(&db_name, &tables_json)).await
+}
+
+#[allow(dead_code)]
+pub async fn insert_async(db_name: &str, table_name: &str, json_data: &str) -> Result<Value, String> {
+  let (db_name, table_name, json_data) = (db_name.to_owned(), table_name.to_owned(), json_data.to_owned());
+  run_blocking(move || insert(&db_name, &table_name, &json_data)).await
+}
+
 #[allow(dead_code)]
 pub async fn query(db_name: &str, sql_query: &str, date_range: Option<HashMap<String, String>>) -> Result<Value, String> {
-  let database_manager = get_database_manager();
-  match database_manager.query(db_name, sql_query, date_range, true).await {
-    Ok(db_manager::DataFusionOutput::Json(data)) => {
-      let json_value = serde_json::to_value(&data).map_err(|e| e.to_string())?;
+  query_with_options(db_name, sql_query, date_range, QueryOptions::default()).await
+}
+
+#[allow(dead_code)]
+pub async fn query_with_options(
+  db_name: &str,
+  sql_query: &str,
+  date_range: Option<HashMap<String, String>>,
+  options: QueryOptions,
+) -> Result<Value, String> {
+  query_on(get_database_manager()?, db_name, sql_query, date_range, options).await
+}
+
+async fn query_on(
+  database_manager: &DatabaseManager,
+  db_name: &str,
+  sql_query: &str,
+  date_range: Option<HashMap<String, String>>,
+  options: QueryOptions,
+) -> Result<Value, String> {
+  let format = options.format;
+  let output = database_manager
+    .query_with_options(db_name, sql_query, date_range, format == OutputFormat::Json, options)
+    .await;
+  match output {
+    Ok((output, warnings)) => {
+      let json_value = query_output_to_value(output, format, database_manager.query_config.non_finite_floats).await?;
       let result = TimonResult {
         status: 200,
         message: format!("query data with success from '{}' with '{}'", db_name, sql_query),
         json_value: Some(json_value),
       };
+      // Files skipped as unreadable are listed next to the rows, empty when the result is complete
+      let mut result = serde_json::to_value(&result).map_err(|e| e.to_string())?;
+      result["warnings"] = serde_json::to_value(&warnings).map_err(|e| e.to_string())?;
+      Ok(result)
+    }
+    Err(err) => query_error_result(err),
+  }
+}
+
+// Same as `query` on the store at `storage_path` instead of the one given to `init_timon`, so a server can route each
+// tenant to its own directory
+#[allow(dead_code)]
+pub async fn query_at(storage_path: &str, db_name: &str, sql_query: &str, date_range: Option<HashMap<String, String>>) -> Result<Value, String> {
+  let database_manager = get_store_manager(storage_path);
+  query_on(&database_manager, db_name, sql_query, date_range, QueryOptions::default()).await
+}
+
+// Same as `query`, returning the rows as `format` ("json", "arrow_ipc" or "csv")
+#[allow(dead_code)]
+pub async fn query_with_format(db_name: &str, sql_query: &str, date_range: Option<HashMap<String, String>>, format: &str) -> Result<Value, String> {
+  match OutputFormat::parse(format) {
+    Ok(format) => {
+      query_with_options(
+        db_name,
+        sql_query,
+        date_range,
+        QueryOptions {
+          format,
+          ..Default::default()
+        },
+      )
+      .await
+    }
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err,
+        json_value: None,
+      };
       serde_json::to_value(&result).map_err(|e| e.to_string())
     }
-    Ok(db_manager::DataFusionOutput::DataFrame(_df)) => Err("DataFrame output is not directly convertible to string".to_owned()),
+  }
+}
+
+// Same as `query_with_options` with the options as JSON, e.g. `{"columns": ["id", "temperature"], "max_rows": 500}`;
+// empty options run the query like `query`
+#[allow(dead_code)]
+pub async fn query_with_options_json(
+  db_name: &str,
+  sql_query: &str,
+  date_range: Option<HashMap<String, String>>,
+  options_json: &str,
+) -> Result<Value, String> {
+  let options = if options_json.trim().is_empty() {
+    Ok(QueryOptions::default())
+  } else {
+    serde_json::from_str::<QueryOptions>(options_json).map_err(|e| format!("Invalid query options: {}", e))
+  };
+  match options {
+    Ok(options) => query_with_options(db_name, sql_query, date_range, options).await,
     Err(err) => {
       let result = TimonResult {
         status: 400,
-        message: err.to_string(),
+        message: err,
         json_value: None,
       };
       serde_json::to_value(&result).map_err(|e| e.to_string())
@@ -242,28 +1146,302 @@ pub async fn query(db_name: &str, sql_query: &str, date_range: Option<HashMap<St
   }
 }
 
+// Same as `query` on a single day of a table (YYYY-MM-DD), reading its file and append segments without enumerating
+// the date range; a day without a file answers 204
+#[allow(dead_code)]
+pub async fn query_day(db_name: &str, table_name: &str, date: &str, sql_query: &str, format: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let batches = match OutputFormat::parse(format) {
+    Ok(format) => database_manager
+      .query_day(db_name, table_name, date, sql_query)
+      .await
+      .map(|batches| (batches, format)),
+    Err(err) => Err(DataFusionError::Plan(err)),
+  };
+  batches_result(
+    batches,
+    database_manager,
+    format!("query data with success from '{}.{}' on {}", db_name, table_name, date),
+  )
+}
+
+// Run `sql_query` over any Parquet file, registered as the table `timon`, e.g. to inspect a file on its own
+#[allow(dead_code)]
+pub async fn query_file(file_path: &str, sql_query: &str, format: &str) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let batches = match OutputFormat::parse(format) {
+    Ok(format) => database_manager.query_file(file_path, sql_query).await.map(|batches| (batches, format)),
+    Err(err) => Err(DataFusionError::Plan(err)),
+  };
+  batches_result(batches, database_manager, format!("query data with success from '{}'", file_path))
+}
+
+// Up to `n` rows of `table_name` in the date range, newest partition first, for a quick look at the data without
+// running a query over the whole range
+#[allow(dead_code)]
+pub fn sample(db_name: &str, table_name: &str, date_range: Option<HashMap<String, String>>, n: usize) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let batches = database_manager
+    .sample(db_name, table_name, date_range, n)
+    .map(|batches| (batches, OutputFormat::Json));
+  batches_result(
+    batches,
+    database_manager,
+    format!("sampled {} rows at most from '{}.{}'", n, db_name, table_name),
+  )
+}
+
+// Number of rows of `table_name` in the date range, in `json_value`, without reading the rows themselves
+#[allow(dead_code)]
+pub async fn count_rows(db_name: &str, table_name: &str, date_range: Option<HashMap<String, String>>) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  match database_manager.count_rows(db_name, table_name, date_range).await {
+    Ok(row_count) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("counted {} rows in '{}.{}'", row_count, db_name, table_name),
+        json_value: Some(json!(row_count)),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => query_error_result(err),
+  }
+}
+
+fn batches_result(
+  batches: Result<(Vec<RecordBatch>, OutputFormat), DataFusionError>,
+  database_manager: &DatabaseManager,
+  message: String,
+) -> Result<Value, String> {
+  match batches {
+    Ok((batches, format)) => {
+      let json_value = record_batches_to_output(&batches, format, database_manager.query_config.non_finite_floats).map_err(|e| e.to_string())?;
+      let result = TimonResult {
+        status: 200,
+        message,
+        json_value: Some(json_value),
+      };
+      serde_json::to_value(&result).map_err(|e| e.to_string())
+    }
+    Err(err) => query_error_result(err),
+  }
+}
+
+// A missing table answers 404 and a defined table without any file in the date range 204, so clients can tell
+// "that table doesn't exist" from "no data for these dates" (files without a matching row answer 200 with no rows)
+fn query_error_status(err: &DataFusionError) -> (u16, String) {
+  if let Some(not_found) = TableNotFound::from_error(err) {
+    (404, not_found.to_string())
+  } else if let Some(no_data) = NoDataInRange::from_error(err) {
+    (204, no_data.to_string())
+  } else {
+    (400, err.to_string())
+  }
+}
+
+fn query_error_result(err: DataFusionError) -> Result<Value, String> {
+  let (status, message) = query_error_status(&err);
+  let result = TimonResult {
+    status,
+    message,
+    json_value: None,
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// Non-JSON formats come back as a DataFrame over the collected results and are encoded here
+async fn query_output_to_value(
+  output: db_manager::DataFusionOutput,
+  format: OutputFormat,
+  non_finite_floats: NonFiniteFloats,
+) -> Result<Value, String> {
+  match output {
+    db_manager::DataFusionOutput::Json(data) => serde_json::to_value(&data).map_err(|e| e.to_string()),
+    db_manager::DataFusionOutput::DataFrame(df) => {
+      let schema = df.schema().inner().clone();
+      let mut batches = df.collect().await.map_err(|e| e.to_string())?;
+      // A result without rows still has its columns, written as the CSV header or the Arrow IPC schema
+      if batches.is_empty() {
+        batches.push(RecordBatch::new_empty(schema));
+      }
+      record_batches_to_output(&batches, format, non_finite_floats).map_err(|e| e.to_string())
+    }
+  }
+}
+
+// Run `sql_query` on `db_name` and persist its result as `dest_db.dest_table`, e.g. to cache a rollup. `options_json`
+// is a `QueryIntoOptions` JSON such as `{"mode": "append", "date_column": "date"}`, or empty to overwrite the table
+#[allow(dead_code)]
+pub async fn query_into(
+  db_name: &str,
+  sql_query: &str,
+  date_range: Option<HashMap<String, String>>,
+  dest_db: &str,
+  dest_table: &str,
+  options_json: &str,
+) -> Result<Value, String> {
+  let database_manager = get_database_manager()?;
+  let options = if options_json.trim().is_empty() {
+    Ok(QueryIntoOptions::default())
+  } else {
+    serde_json::from_str::<QueryIntoOptions>(options_json).map_err(|e| format!("Invalid query_into options: {}", e))
+  };
+  let materialized = match options {
+    Ok(options) => database_manager
+      .clone()
+      .query_into(db_name, sql_query, date_range, dest_db, dest_table, options)
+      .await
+      .map_err(materialize_error_status),
+    Err(err) => Err((400, err)),
+  };
+  materialized_result(materialized)
+}
+
+// Create `table_name` from the result of `select_sql`, e.g. a table joining two others for a screen to read cheaply:
+// `SELECT o.id, o.total, c.name FROM orders o JOIN customers c ON o.customer_id = c.id`. Fails if the table exists.
+#[allow(dead_code)]
+pub async fn create_table_as(
+  db_name: &str,
+  table_name: &str,
+  date_range: Option<HashMap<String, String>>,
+  select_sql: &str,
+) -> Result<Value, String> {
+  let materialized = get_database_manager()?
+    .clone()
+    .create_table_as(db_name, table_name, date_range, select_sql)
+    .await
+    .map_err(materialize_error_status);
+  materialized_result(materialized)
+}
+
+// The status of a failed `query_into`/`create_table_as`: the query's status if the query failed, 507 over the quota, 400
+// otherwise
+fn materialize_error_status(err: Box<dyn std::error::Error>) -> (u16, String) {
+  match err.downcast_ref::<DataFusionError>() {
+    Some(query_err) => query_error_status(query_err),
+    None if err.is::<QuotaExceeded>() => (507, err.to_string()),
+    None => (400, err.to_string()),
+  }
+}
+
+fn materialized_result(materialized: Result<(String, db_manager::InsertSummary), (u16, String)>) -> Result<Value, String> {
+  let result = match materialized {
+    Ok((message, summary)) => TimonResult {
+      status: 200,
+      message,
+      json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
+    },
+    Err((status, message)) => TimonResult {
+      status,
+      message,
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[allow(dead_code)]
+pub async fn explain(db_name: &str, sql_query: &str, date_range: Option<HashMap<String, String>>, analyze: bool) -> Result<Value, String> {
+  match get_database_manager()?.explain(db_name, sql_query, date_range, analyze).await {
+    Ok((plan, warnings)) => {
+      let result = TimonResult {
+        status: 200,
+        message: format!("query plan for '{}' on '{}'", sql_query, db_name),
+        json_value: Some(Value::String(plan)),
+      };
+      // Same shape as `query`: the files left out of the plan as unreadable
+      let mut result = serde_json::to_value(&result).map_err(|e| e.to_string())?;
+      result["warnings"] = serde_json::to_value(&warnings).map_err(|e| e.to_string())?;
+      Ok(result)
+    }
+    Err(err) => query_error_result(err),
+  }
+}
+
 /* ******************************** S3 Compatible Storage ********************************
-* @ init_bucket(bucket_endpoint, bucket_name, access_key_id, secret_access_key)
-* @ query_bucket(bucket_name, date_range, sql_query)
-* @ sink_daily_parquet(db_name, table_name)
+* @ init_bucket(bucket_endpoint, bucket_name, access_key_id, secret_access_key) & reinit_bucket(...)
+* @ init_bucket_with_config(bucket_endpoint, bucket_name, access_key_id, secret_access_key, client_config_json)
+* @ query_bucket(bucket_name, date_range, sql_query) & query_bucket_with_format(date_range, sql_query, format)
+* @ query_bucket_with_read_options(date_range, sql_query, format, read_options_json)
+* @ query_hybrid(db_name, table_name, date_range, sql_query, local_retention_days, format)
+* @ sink_daily_parquet(db_name, table_name) & sink_range(db_name, table_name, start_date, end_date, granularity)
+* @ put_object(key, bytes) & get_object(key)
+* @ restore(db_name, mode) & available_dates_bucket(table_name)
+* @ set_sync_progress_callback(callback)
  */
 
-static CLOUD_STORAGE_MANAGER: OnceLock<CloudStorageManager> = OnceLock::new();
+// Swappable so `reinit_bucket` can switch buckets or rotate credentials; in-flight calls keep the manager they started with
+static CLOUD_STORAGE_MANAGER: OnceLock<RwLock<Arc<CloudStorageManager>>> = OnceLock::new();
 
-fn get_cloud_storage_manager() -> &'static CloudStorageManager {
-  CLOUD_STORAGE_MANAGER.get().expect("CloudStorageManager is not initialized")
+fn get_cloud_storage_manager() -> Result<Arc<CloudStorageManager>, TimonError> {
+  let cloud_storage_manager = CLOUD_STORAGE_MANAGER.get().ok_or(TimonError::NotInitialized {
+    manager: "CloudStorageManager",
+    init_function: "init_bucket",
+  })?;
+  Ok(cloud_storage_manager.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone())
+}
+
+// Progress callback of the sinks and restores started after it is registered; kept across `reinit_bucket`
+static SYNC_PROGRESS_CALLBACK: RwLock<Option<ProgressCallback>> = RwLock::new(None);
+
+fn get_sync_progress_callback() -> Option<ProgressCallback> {
+  SYNC_PROGRESS_CALLBACK.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+// Register the callback told about each file a sink or restore handles (see `cloud_sync::SyncProgress`), or remove it with `None`
+#[allow(dead_code)]
+pub fn set_sync_progress_callback(callback: Option<ProgressCallback>) -> Result<Value, String> {
+  let message = if callback.is_some() {
+    "sync progress callback registered"
+  } else {
+    "sync progress callback removed"
+  };
+  *SYNC_PROGRESS_CALLBACK.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = callback;
+  let result = TimonResult {
+    status: 200,
+    message: message.to_owned(),
+    json_value: None,
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
 }
 
 pub fn init_bucket(bucket_endpoint: &str, bucket_name: &str, access_key_id: &str, secret_access_key: &str) -> Result<Value, String> {
-  let cloud_storage_manager = cloud_sync::CloudStorageManager::new(
-    get_database_manager().clone(),
+  init_bucket_with_config(bucket_endpoint, bucket_name, access_key_id, secret_access_key, "")
+}
+
+// Same as `init_bucket`, with the HTTP client's timeouts and retries given as JSON (every key optional, empty for the defaults)
+pub fn init_bucket_with_config(
+  bucket_endpoint: &str,
+  bucket_name: &str,
+  access_key_id: &str,
+  secret_access_key: &str,
+  client_config_json: &str,
+) -> Result<Value, String> {
+  let client_config = if client_config_json.trim().is_empty() {
+    BucketClientConfig::default()
+  } else {
+    match serde_json::from_str::<BucketClientConfig>(client_config_json) {
+      Ok(client_config) => client_config,
+      Err(e) => {
+        let result = TimonResult {
+          status: 400,
+          message: format!("Invalid bucket client config: {}", e),
+          json_value: None,
+        };
+        return serde_json::to_value(&result).map_err(|e| e.to_string());
+      }
+    }
+  };
+  let cloud_storage_manager = cloud_sync::CloudStorageManager::new_with_config(
+    get_database_manager()?.clone(),
     Some(bucket_endpoint),
     Some(access_key_id),
     Some(secret_access_key),
     Some(bucket_name),
+    client_config,
   );
 
-  match CLOUD_STORAGE_MANAGER.set(cloud_storage_manager) {
+  match CLOUD_STORAGE_MANAGER.set(RwLock::new(Arc::new(cloud_storage_manager))) {
     Ok(_) => {
       let result = TimonResult {
         status: 200,
@@ -283,55 +1461,251 @@ pub fn init_bucket(bucket_endpoint: &str, bucket_name: &str, access_key_id: &str
   }
 }
 
+#[allow(dead_code)]
+pub fn reinit_bucket(bucket_endpoint: &str, bucket_name: &str, access_key_id: &str, secret_access_key: &str) -> Result<Value, String> {
+  // Keep the client timeouts and retries the bucket was initialized with
+  let client_config = get_cloud_storage_manager()
+    .map(|current| current.client_config.clone())
+    .unwrap_or_default();
+  let cloud_storage_manager = Arc::new(cloud_sync::CloudStorageManager::new_with_config(
+    get_database_manager()?.clone(),
+    Some(bucket_endpoint),
+    Some(access_key_id),
+    Some(secret_access_key),
+    Some(bucket_name),
+    client_config,
+  ));
+
+  // Initialize on first use, otherwise replace the current manager
+  let current = CLOUD_STORAGE_MANAGER.get_or_init(|| RwLock::new(cloud_storage_manager.clone()));
+  *current.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = cloud_storage_manager;
+
+  let result = TimonResult {
+    status: 200,
+    message: format!("CloudStorageManager reinitialized successfully with '{}' bucket", bucket_name),
+    json_value: None,
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
 pub async fn query_bucket(date_range: HashMap<&str, &str>, sql_query: &str) -> Result<Value, String> {
-  let cloud_storage_manager = get_cloud_storage_manager();
-  let mut converted_date_range: HashMap<String, String> = HashMap::new(); // TODO: remove converted_date_range
-  for (key, value) in date_range {
-    converted_date_range.insert(key.to_string(), value.to_string());
-  }
-  match cloud_storage_manager.query_bucket(converted_date_range, &sql_query, true).await {
-    Ok(db_manager::DataFusionOutput::Json(data)) => {
-      let json_value = serde_json::to_value(&data).map_err(|e| e.to_string())?;
+  query_bucket_with_format(date_range, sql_query, "json").await
+}
+
+// Same as `query_bucket`, returning the rows as `format` ("json", "arrow_ipc" or "csv")
+// Query a table's last `local_retention_days` days from the local files and the older days of the range from the bucket
+// as one table, returning the rows as `format` ("json", "arrow_ipc" or "csv")
+#[allow(dead_code)]
+pub async fn query_hybrid(
+  db_name: &str,
+  table_name: &str,
+  date_range: HashMap<&str, &str>,
+  sql_query: &str,
+  local_retention_days: u64,
+  format: &str,
+) -> Result<Value, String> {
+  let format = match OutputFormat::parse(format) {
+    Ok(format) => format,
+    Err(err) => {
+      let result = TimonResult {
+        status: 400,
+        message: err,
+        json_value: None,
+      };
+      return serde_json::to_value(&result).map_err(|e| e.to_string());
+    }
+  };
+  let database_manager = get_database_manager()?;
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  let date_range: HashMap<String, String> = date_range.into_iter().map(|(key, value)| (key.to_owned(), value.to_owned())).collect();
+  match cloud_storage_manager
+    .query_hybrid(database_manager, db_name, table_name, date_range, sql_query, local_retention_days)
+    .await
+  {
+    Ok(df) => {
+      let json_value = query_output_to_value(
+        db_manager::DataFusionOutput::DataFrame(df),
+        format,
+        database_manager.query_config.non_finite_floats,
+      )
+      .await?;
       let result = TimonResult {
         status: 200,
         message: format!(
-          "query data with success from '{}' with '{}'",
-          cloud_storage_manager.bucket_name, sql_query
+          "query data with success from '{}' and '{}' with '{}'",
+          db_name, cloud_storage_manager.bucket_name, sql_query
         ),
         json_value: Some(json_value),
       };
       serde_json::to_value(&result).map_err(|e| e.to_string())
     }
-    Ok(db_manager::DataFusionOutput::DataFrame(_df)) => {
+    Err(err) => query_error_result(err),
+  }
+}
+
+pub async fn query_bucket_with_format(date_range: HashMap<&str, &str>, sql_query: &str, format: &str) -> Result<Value, String> {
+  query_bucket_with_read_options(date_range, sql_query, format, "").await
+}
+
+// Same as `query_bucket_with_format`, scanning the objects with the `ReadOptions` given as JSON (e.g. their schema)
+pub async fn query_bucket_with_read_options(
+  date_range: HashMap<&str, &str>,
+  sql_query: &str,
+  format: &str,
+  read_options_json: &str,
+) -> Result<Value, String> {
+  let read_options = if read_options_json.trim().is_empty() {
+    Ok(ReadOptions::default())
+  } else {
+    serde_json::from_str::<ReadOptions>(read_options_json).map_err(|e| format!("Invalid read options: {}", e))
+  };
+  let (format, read_options) = match OutputFormat::parse(format).and_then(|format| Ok((format, read_options?))) {
+    Ok(parsed) => parsed,
+    Err(err) => {
       let result = TimonResult {
         status: 400,
-        message: "DataFrame output is not directly convertible to string".to_owned(),
+        message: err,
         json_value: None,
       };
+      return serde_json::to_value(&result).map_err(|e| e.to_string());
+    }
+  };
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  let mut converted_date_range: HashMap<String, String> = HashMap::new(); // TODO: remove converted_date_range
+  for (key, value) in date_range {
+    converted_date_range.insert(key.to_string(), value.to_string());
+  }
+  match cloud_storage_manager
+    .query_bucket(converted_date_range, sql_query, format == OutputFormat::Json, &read_options)
+    .await
+  {
+    Ok(output) => {
+      let json_value = query_output_to_value(output, format, get_database_manager()?.query_config.non_finite_floats).await?;
+      let result = TimonResult {
+        status: 200,
+        message: format!(
+          "query data with success from '{}' with '{}'",
+          cloud_storage_manager.bucket_name, sql_query
+        ),
+        json_value: Some(json_value),
+      };
       serde_json::to_value(&result).map_err(|e| e.to_string())
     }
-    Err(err) => {
+    Err(err) => query_error_result(err),
+  }
+}
+
+#[allow(dead_code)]
+pub async fn put_object(key: &str, bytes: Vec<u8>) -> Result<Value, String> {
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  let byte_count = bytes.len();
+  let result = match cloud_storage_manager.put_object(key, bytes).await {
+    Ok(_) => TimonResult {
+      status: 200,
+      message: format!("stored {} bytes at '{}' in '{}'", byte_count, key, cloud_storage_manager.bucket_name),
+      json_value: None,
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// The object's bytes are returned base64 encoded in `json_value`
+#[allow(dead_code)]
+pub async fn get_object(key: &str) -> Result<Value, String> {
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  let result = match cloud_storage_manager.get_object(key).await {
+    Ok(bytes) => TimonResult {
+      status: 200,
+      message: format!("fetched '{}' from '{}'", key, cloud_storage_manager.bucket_name),
+      json_value: Some(Value::String(general_purpose::STANDARD.encode(bytes))),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err.to_string(),
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// Rebuild `db_name` locally from the bucket; `mode` ("error", "merge" or "overwrite") decides what happens when it already exists
+#[allow(dead_code)]
+pub async fn restore(db_name: &str, mode: &str) -> Result<Value, String> {
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  let restored = match cloud_sync::RestoreMode::parse(mode) {
+    Ok(mode) => cloud_storage_manager
+      .restore(db_name, mode, get_sync_progress_callback().as_ref())
+      .await
+      .map_err(|e| e.to_string()),
+    Err(err) => Err(err),
+  };
+  let result = match restored {
+    Ok(summary) => TimonResult {
+      status: 200,
+      message: format!(
+        "restored '{}' from '{}': {} files downloaded, {} kept",
+        db_name,
+        cloud_storage_manager.bucket_name,
+        summary.downloaded.len(),
+        summary.skipped.len()
+      ),
+      json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err,
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+// Same as `available_dates`, for the objects of a table in the bucket
+#[allow(dead_code)]
+pub async fn available_dates_bucket(table_name: &str) -> Result<Value, String> {
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  match cloud_storage_manager.available_dates(table_name).await {
+    Ok(dates) => {
       let result = TimonResult {
-        status: 400,
-        message: err.to_string(),
-        json_value: None,
+        status: 200,
+        message: format!(
+          "'{}' has data on {} dates in '{}'",
+          table_name,
+          dates.len(),
+          cloud_storage_manager.bucket_name
+        ),
+        json_value: Some(json!(dates)),
       };
       serde_json::to_value(&result).map_err(|e| e.to_string())
     }
+    Err(err) => query_error_result(err),
   }
 }
 
 pub async fn sink_daily_parquet(db_name: &str, table_name: &str) -> Result<Value, String> {
-  let cloud_storage_manager = get_cloud_storage_manager();
-  match cloud_storage_manager.sink_daily_parquet(db_name, table_name).await {
-    Ok(_) => {
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  match cloud_storage_manager
+    .sink_daily_parquet(db_name, table_name, get_sync_progress_callback().as_ref())
+    .await
+  {
+    Ok(summary) => {
       let result = TimonResult {
-        status: 200,
+        status: if summary.failed.is_empty() { 200 } else { 400 },
         message: format!(
-          "successfully uploaded '{}.{}' table data to '{}' bucket",
-          db_name, table_name, cloud_storage_manager.bucket_name
+          "uploaded {} file(s), skipped {}, failed {} for '{}.{}' table to '{}' bucket",
+          summary.uploaded.len(),
+          summary.skipped.len(),
+          summary.failed.len(),
+          db_name,
+          table_name,
+          cloud_storage_manager.bucket_name
         ),
-        json_value: None,
+        json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
       };
       serde_json::to_value(&result).map_err(|e| e.to_string())
     }
@@ -345,3 +1719,156 @@ pub async fn sink_daily_parquet(db_name: &str, table_name: &str) -> Result<Value
     }
   }
 }
+
+// Upload the daily (or monthly) files of a table between two dates (`YYYY-MM-DD`, `*` or empty for an open side), even
+// those synced before, so a corrected range can be re-synced without the rest of the table
+#[allow(dead_code)]
+pub async fn sink_range(db_name: &str, table_name: &str, start_date: &str, end_date: &str, granularity: &str) -> Result<Value, String> {
+  let cloud_storage_manager = get_cloud_storage_manager()?;
+  let mut date_range = HashMap::new();
+  date_range.insert("start_date".to_owned(), if start_date.is_empty() { "*" } else { start_date }.to_owned());
+  date_range.insert("end_date".to_owned(), if end_date.is_empty() { "*" } else { end_date }.to_owned());
+
+  let sunk = match (
+    helpers::parse_date_bounds(Some(&date_range)),
+    cloud_sync::SinkGranularity::parse(granularity),
+  ) {
+    (Ok((start, end)), Ok(granularity)) => cloud_storage_manager
+      .sink_range(db_name, table_name, start, end, granularity, get_sync_progress_callback().as_ref())
+      .await
+      .map_err(|e| e.to_string()),
+    (Err(err), _) => Err(format!("Invalid date range: {}", err)),
+    (_, Err(err)) => Err(err),
+  };
+  let result = match sunk {
+    Ok(summary) => TimonResult {
+      status: if summary.failed.is_empty() { 200 } else { 400 },
+      message: format!(
+        "uploaded {} file(s), failed {} for '{}.{}' table between {} and {} to '{}' bucket",
+        summary.uploaded.len(),
+        summary.failed.len(),
+        db_name,
+        table_name,
+        date_range["start_date"],
+        date_range["end_date"],
+        cloud_storage_manager.bucket_name
+      ),
+      json_value: Some(serde_json::to_value(&summary).map_err(|e| e.to_string())?),
+    },
+    Err(err) => TimonResult {
+      status: 400,
+      message: err,
+      json_value: None,
+    },
+  };
+  serde_json::to_value(&result).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // No test of this crate calls `init_timon`, whose manager would outlive the test in this process
+  #[test]
+  fn create_database_before_init_timon_is_an_error() {
+    let error = create_database("db").unwrap_err();
+    assert_eq!(error, "DatabaseManager is not initialized, call 'init_timon' first");
+  }
+
+  #[test]
+  fn envelope_serializes_its_version_first() {
+    let result = TimonResult {
+      status: 200,
+      message: "ok".to_owned(),
+      json_value: Some(json!([1])),
+    };
+    assert_eq!(
+      serde_json::to_string(&result).unwrap(),
+      format!(
+        r#"{{"version":{},"status":200,"message":"ok","json_value":[1]}}"#,
+        RESULT_ENVELOPE_VERSION
+      )
+    );
+    let empty = TimonResult {
+      status: 204,
+      message: String::new(),
+      json_value: None,
+    };
+    assert_eq!(
+      serde_json::to_value(&empty).unwrap(),
+      json!({"version": RESULT_ENVELOPE_VERSION, "status": 204, "message": "", "json_value": null})
+    );
+  }
+
+  #[test]
+  fn ffi_responses_are_envelopes_whether_or_not_the_engine_answered() {
+    // What the Android and iOS exports return for an engine answer, an engine failure and a call before `init_timon`
+    let answer = ffi_response(list_databases_result(), "Failed to list databases");
+    let answer: Value = serde_json::from_str(&answer).unwrap();
+    assert_eq!(
+      answer,
+      json!({"version": RESULT_ENVELOPE_VERSION, "status": 200, "message": "ok", "json_value": ["db"]})
+    );
+
+    let before_init: Value = serde_json::from_str(&ffi_response(list_databases(), "Failed to list databases")).unwrap();
+    assert_eq!(
+      before_init,
+      json!({
+        "version": RESULT_ENVELOPE_VERSION,
+        "status": 400,
+        "message": "Failed to list databases: DatabaseManager is not initialized, call 'init_timon' first",
+        "json_value": null
+      })
+    );
+    assert_eq!(
+      ffi_response(Err("invalid date range".to_owned()), "Failed to query"),
+      ffi_error("Failed to query: invalid date range")
+    );
+  }
+
+  fn list_databases_result() -> Result<Value, String> {
+    let result = TimonResult {
+      status: 200,
+      message: "ok".to_owned(),
+      json_value: Some(json!(["db"])),
+    };
+    serde_json::to_value(&result).map_err(|e| e.to_string())
+  }
+
+  #[tokio::test]
+  async fn empty_results_keep_their_columns_in_csv_and_arrow_ipc() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut manager = DatabaseManager::new(dir.path().to_str().unwrap());
+    manager.create_database("db").unwrap();
+    manager
+      .create_table("db", "t", r#"{"id": {"type": "int"}, "v": {"type": "string"}}"#)
+      .unwrap();
+    manager.insert("db", "t", r#"[{"id": 1, "v": "a"}]"#).unwrap();
+
+    let empty_result = || async { manager.query("db", "SELECT id, v FROM t WHERE 1 = 0", None, false).await.unwrap() };
+    let csv = query_output_to_value(empty_result().await, OutputFormat::Csv, NonFiniteFloats::default()).await;
+    assert_eq!(csv.unwrap(), json!("id,v\n"));
+
+    let arrow_ipc = query_output_to_value(empty_result().await, OutputFormat::ArrowIpc, NonFiniteFloats::default()).await;
+    let bytes = general_purpose::STANDARD.decode(arrow_ipc.unwrap().as_str().unwrap()).unwrap();
+    let reader = arrow::ipc::reader::StreamReader::try_new(bytes.as_slice(), None).unwrap();
+    let columns: Vec<String> = reader.schema().fields().iter().map(|field| field.name().clone()).collect();
+    assert_eq!(columns, ["id", "v"]);
+    assert_eq!(reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>(), 0);
+  }
+
+  #[test]
+  fn upsert_without_unique_fields_answers_400() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut manager = DatabaseManager::new(dir.path().to_str().unwrap());
+    manager.create_database("db").unwrap();
+    manager.create_table("db", "t", r#"{"id": {"type": "int"}}"#).unwrap();
+
+    let result = insert_result(manager.upsert("db", "t", r#"[{"id": 1}]"#)).unwrap();
+    assert_eq!(result["status"], 400);
+    assert_eq!(
+      result["message"],
+      "Table 't' has no unique fields to upsert on, use insert_with_mode with the key fields."
+    );
+  }
+}
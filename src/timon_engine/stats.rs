@@ -0,0 +1,150 @@
+use serde::Serialize;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// Upper bounds (inclusive, in milliseconds) of the query latency buckets; slower queries land in a last, unbounded one
+pub const QUERY_LATENCY_BUCKETS_MS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1_000, 5_000];
+
+// Aggregate counters of the process's inserts, queries and bucket transfers since it started, for an embedder's
+// dashboards. Every counter only grows, so rates (inserts/sec) come from the difference between two snapshots.
+struct Stats {
+  inserts: AtomicU64,
+  insert_errors: AtomicU64,
+  rows_received: AtomicU64,
+  rows_written: AtomicU64,
+  queries: AtomicU64,
+  query_errors: AtomicU64,
+  query_time_ms: AtomicU64,
+  query_latency: [AtomicU64; QUERY_LATENCY_BUCKETS_MS.len() + 1],
+  files_read: AtomicU64,
+  bytes_read: AtomicU64,
+  files_skipped: AtomicU64,
+  bucket_files_read: AtomicU64,
+  bucket_bytes_downloaded: AtomicU64,
+  bucket_files_uploaded: AtomicU64,
+  bucket_bytes_uploaded: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static STATS: Stats = Stats {
+  inserts: ZERO,
+  insert_errors: ZERO,
+  rows_received: ZERO,
+  rows_written: ZERO,
+  queries: ZERO,
+  query_errors: ZERO,
+  query_time_ms: ZERO,
+  query_latency: [ZERO; QUERY_LATENCY_BUCKETS_MS.len() + 1],
+  files_read: ZERO,
+  bytes_read: ZERO,
+  files_skipped: ZERO,
+  bucket_files_read: ZERO,
+  bucket_bytes_downloaded: ZERO,
+  bucket_files_uploaded: ZERO,
+  bucket_bytes_uploaded: ZERO,
+};
+
+// Queries whose latency fell in a bucket; `le` is the bucket's upper bound, unset for the last one
+#[derive(Serialize, Debug, Clone)]
+pub struct LatencyBucket {
+  pub le: Option<u64>,
+  pub count: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StatsSnapshot {
+  pub inserts: u64,       // Successful insert batches (an NDJSON chunk or a day of `queryInto` counts as one)
+  pub insert_errors: u64, // Insert batches that failed, validation included
+  pub rows_received: u64,
+  pub rows_written: u64, // Rows inserted or updated
+  pub queries: u64,
+  pub query_errors: u64,
+  pub query_time_ms: u64, // Total time spent in queries, failed ones included
+  pub query_latency_ms: Vec<LatencyBucket>,
+  pub files_read: u64, // Local Parquet files registered by queries
+  pub bytes_read: u64, // Size on disk of those files
+  pub files_skipped: u64,
+  pub bucket_files_read: u64, // Objects registered by bucket queries
+  pub bucket_bytes_downloaded: u64,
+  pub bucket_files_uploaded: u64,
+  pub bucket_bytes_uploaded: u64,
+}
+
+fn add(counter: &AtomicU64, value: u64) {
+  counter.fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn record_insert(rows_received: usize, rows_written: Option<usize>) {
+  add(&STATS.rows_received, rows_received as u64);
+  match rows_written {
+    Some(rows_written) => {
+      add(&STATS.inserts, 1);
+      add(&STATS.rows_written, rows_written as u64);
+    }
+    None => add(&STATS.insert_errors, 1),
+  }
+}
+
+pub fn record_query(started: Instant, succeeded: bool) {
+  let elapsed_ms = started.elapsed().as_millis() as u64;
+  add(&STATS.queries, 1);
+  if !succeeded {
+    add(&STATS.query_errors, 1);
+  }
+  add(&STATS.query_time_ms, elapsed_ms);
+  let bucket = QUERY_LATENCY_BUCKETS_MS
+    .iter()
+    .position(|le| elapsed_ms <= *le)
+    .unwrap_or(QUERY_LATENCY_BUCKETS_MS.len());
+  add(&STATS.query_latency[bucket], 1);
+}
+
+pub fn record_file_read(file_path: &str) {
+  add(&STATS.files_read, 1);
+  add(&STATS.bytes_read, fs::metadata(file_path).map_or(0, |metadata| metadata.len()));
+}
+
+pub fn record_file_skipped() {
+  add(&STATS.files_skipped, 1);
+}
+
+pub fn record_bucket_file_read() {
+  add(&STATS.bucket_files_read, 1);
+}
+
+pub fn record_bucket_download(bytes: usize) {
+  add(&STATS.bucket_bytes_downloaded, bytes as u64);
+}
+
+pub fn record_bucket_upload(bytes: usize) {
+  add(&STATS.bucket_files_uploaded, 1);
+  add(&STATS.bucket_bytes_uploaded, bytes as u64);
+}
+
+pub fn snapshot() -> StatsSnapshot {
+  let read = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+  let bounds = QUERY_LATENCY_BUCKETS_MS.iter().copied().map(Some).chain([None]);
+  StatsSnapshot {
+    inserts: read(&STATS.inserts),
+    insert_errors: read(&STATS.insert_errors),
+    rows_received: read(&STATS.rows_received),
+    rows_written: read(&STATS.rows_written),
+    queries: read(&STATS.queries),
+    query_errors: read(&STATS.query_errors),
+    query_time_ms: read(&STATS.query_time_ms),
+    query_latency_ms: bounds
+      .zip(&STATS.query_latency)
+      .map(|(le, count)| LatencyBucket { le, count: read(count) })
+      .collect(),
+    files_read: read(&STATS.files_read),
+    bytes_read: read(&STATS.bytes_read),
+    files_skipped: read(&STATS.files_skipped),
+    bucket_files_read: read(&STATS.bucket_files_read),
+    bucket_bytes_downloaded: read(&STATS.bucket_bytes_downloaded),
+    bucket_files_uploaded: read(&STATS.bucket_files_uploaded),
+    bucket_bytes_uploaded: read(&STATS.bucket_bytes_uploaded),
+  }
+}
@@ -0,0 +1,152 @@
+use arrow::array::{Array, ArrayRef, Float64Array, Int64Array, StringArray};
+use arrow::datatypes::DataType;
+use datafusion::common::cast::{as_float64_array, as_int64_array};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::logical_expr::{create_udf, ColumnarValue, ScalarUDF, Volatility};
+use std::sync::Arc;
+
+/* ******************************** Built-in UDFs ********************************
+* Scalar functions every query session gets on top of DataFusion's, see `DatabaseManager::session_context`.
+* Arguments are coerced to the declared types, so integer columns work where floats are expected.
+* @ geohash(lat, lon, precision) -> string, the base32 geohash cell with `precision` characters (1 to 12)
+* @ haversine_km(lat1, lon1, lat2, lon2) -> float, the great-circle distance between two points in kilometers
+* @ celsius_to_fahrenheit(celsius) & fahrenheit_to_celsius(fahrenheit) -> float
+ */
+pub fn builtin_udfs() -> Vec<ScalarUDF> {
+  vec![
+    create_udf(
+      "geohash",
+      vec![DataType::Float64, DataType::Float64, DataType::Int64],
+      Arc::new(DataType::Utf8),
+      Volatility::Immutable,
+      Arc::new(geohash_udf),
+    ),
+    create_udf(
+      "haversine_km",
+      vec![DataType::Float64; 4],
+      Arc::new(DataType::Float64),
+      Volatility::Immutable,
+      Arc::new(haversine_km_udf),
+    ),
+    create_udf(
+      "celsius_to_fahrenheit",
+      vec![DataType::Float64],
+      Arc::new(DataType::Float64),
+      Volatility::Immutable,
+      Arc::new(|args: &[ColumnarValue]| map_float64(args, |celsius| celsius * 9.0 / 5.0 + 32.0)),
+    ),
+    create_udf(
+      "fahrenheit_to_celsius",
+      vec![DataType::Float64],
+      Arc::new(DataType::Float64),
+      Volatility::Immutable,
+      Arc::new(|args: &[ColumnarValue]| map_float64(args, |fahrenheit| (fahrenheit - 32.0) * 5.0 / 9.0)),
+    ),
+  ]
+}
+
+// Apply `f` to every value of a single Float64 argument, nulls staying null
+fn map_float64(args: &[ColumnarValue], f: impl Fn(f64) -> f64) -> DataFusionResult<ColumnarValue> {
+  let arrays = ColumnarValue::values_to_arrays(args)?;
+  let values = as_float64_array(&arrays[0])?;
+  let result: Float64Array = values.iter().map(|value| value.map(&f)).collect();
+  Ok(ColumnarValue::Array(Arc::new(result) as ArrayRef))
+}
+
+fn geohash_udf(args: &[ColumnarValue]) -> DataFusionResult<ColumnarValue> {
+  let arrays = ColumnarValue::values_to_arrays(args)?;
+  let (lats, lons, precisions): (&Float64Array, &Float64Array, &Int64Array) =
+    (as_float64_array(&arrays[0])?, as_float64_array(&arrays[1])?, as_int64_array(&arrays[2])?);
+  let result: StringArray = (0..lats.len())
+    .map(|i| match (lats.is_valid(i), lons.is_valid(i), precisions.is_valid(i)) {
+      (true, true, true) => geohash(lats.value(i), lons.value(i), precisions.value(i)),
+      _ => None,
+    })
+    .collect();
+  Ok(ColumnarValue::Array(Arc::new(result) as ArrayRef))
+}
+
+// Interleave longitude and latitude bisections, 5 bits per base32 character; out of range inputs give null
+fn geohash(lat: f64, lon: f64, precision: i64) -> Option<String> {
+  const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+  if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) || !(1..=12).contains(&precision) {
+    return None;
+  }
+  let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+  let mut hash = String::with_capacity(precision as usize);
+  let (mut bits, mut bit_count, mut even) = (0usize, 0, true);
+  while hash.len() < precision as usize {
+    let (range, value): (&mut (f64, f64), f64) = if even { (&mut lon_range, lon) } else { (&mut lat_range, lat) };
+    let mid = (range.0 + range.1) / 2.0;
+    bits <<= 1;
+    if value >= mid {
+      bits |= 1;
+      range.0 = mid;
+    } else {
+      range.1 = mid;
+    }
+    even = !even;
+    bit_count += 1;
+    if bit_count == 5 {
+      hash.push(BASE32[bits] as char);
+      (bits, bit_count) = (0, 0);
+    }
+  }
+  Some(hash)
+}
+
+fn haversine_km_udf(args: &[ColumnarValue]) -> DataFusionResult<ColumnarValue> {
+  const EARTH_RADIUS_KM: f64 = 6371.0088;
+  let arrays = ColumnarValue::values_to_arrays(args)?;
+  let columns = arrays.iter().map(|array| as_float64_array(array)).collect::<DataFusionResult<Vec<_>>>()?;
+  let result: Float64Array = (0..columns[0].len())
+    .map(|i| {
+      if columns.iter().any(|column| column.is_null(i)) {
+        return None;
+      }
+      let (lat1, lon1, lat2, lon2) = (
+        columns[0].value(i).to_radians(),
+        columns[1].value(i).to_radians(),
+        columns[2].value(i).to_radians(),
+        columns[3].value(i).to_radians(),
+      );
+      let a = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+      Some(2.0 * EARTH_RADIUS_KM * a.sqrt().asin())
+    })
+    .collect();
+  Ok(ColumnarValue::Array(Arc::new(result) as ArrayRef))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::timon_engine::db_manager::{DataFusionOutput, DatabaseManager};
+  use serde_json::json;
+  use tempfile::TempDir;
+
+  #[tokio::test]
+  async fn queries_call_builtin_and_embedder_udfs() {
+    let dir = TempDir::new().unwrap();
+    let mut manager = DatabaseManager::new(dir.path().to_str().unwrap());
+    manager.create_database("db").unwrap();
+    let schema = r#"{"lat": {"type": "float"}, "lon": {"type": "float"}, "celsius": {"type": "int"}}"#;
+    manager.create_table("db", "places", schema).unwrap();
+    manager
+      .insert("db", "places", &json!([{"lat": 57.64911, "lon": 10.40744, "celsius": 100}]).to_string())
+      .unwrap();
+    manager.udfs.push(create_udf(
+      "double",
+      vec![DataType::Float64],
+      Arc::new(DataType::Float64),
+      Volatility::Immutable,
+      Arc::new(|args: &[ColumnarValue]| map_float64(args, |value| value * 2.0)),
+    ));
+
+    let sql_query = "SELECT geohash(lat, lon, 11) AS cell, celsius_to_fahrenheit(celsius) AS fahrenheit, double(lat) AS doubled FROM places";
+    let DataFusionOutput::Json(rows) = manager.query("db", sql_query, None, true).await.unwrap() else {
+      panic!("expected JSON rows");
+    };
+    assert_eq!(rows, json!([{"cell": "u4pruydqqvj", "fahrenheit": 212.0, "doubled": 115.29822}]));
+    assert_eq!(geohash(91.0, 0.0, 5), None::<String>);
+  }
+}